@@ -14,3 +14,78 @@ CREATE TABLE IF NOT EXISTS lastfm_usernames (
 	discord_username VARCHAR(45),
 	lastfm_username VARCHAR(50)
 );
+
+ALTER TABLE lastfm_usernames ADD COLUMN IF NOT EXISTS session_key VARCHAR(64);
+ALTER TABLE lastfm_usernames ADD COLUMN IF NOT EXISTS service VARCHAR(2083);
+
+
+CREATE TABLE IF NOT EXISTS listenbrainz_usernames (
+	discord_username VARCHAR(45),
+	listenbrainz_username VARCHAR(64)
+);
+
+
+CREATE TABLE IF NOT EXISTS lastfm_scrobbles (
+	service VARCHAR(2083) NOT NULL DEFAULT '',
+	lastfm_username VARCHAR(50) NOT NULL,
+	scrobbled_at BIGINT NOT NULL,
+	artist VARCHAR(1024) NOT NULL,
+	album VARCHAR(1024),
+	track VARCHAR(1024) NOT NULL,
+	url VARCHAR(2083),
+	-- stored once however many syncs of the same user overlap. scrobbles can share a second, so the track is part of it
+	UNIQUE (service, lastfm_username, scrobbled_at, artist, track)
+);
+
+
+CREATE TABLE IF NOT EXISTS lastfm_sync_status (
+	service VARCHAR(2083) NOT NULL DEFAULT '',
+	lastfm_username VARCHAR(50),
+	last_synced BIGINT,
+	last_attempt BIGINT,
+	failures INTEGER DEFAULT 0,
+	last_error VARCHAR(2083),
+	PRIMARY KEY (service, lastfm_username)
+);
+
+
+CREATE TABLE IF NOT EXISTS privacy_settings (
+	discord_username VARCHAR(45) PRIMARY KEY,
+	public BOOLEAN
+);
+
+
+CREATE TABLE IF NOT EXISTS user_timezones (
+	discord_username VARCHAR(45) PRIMARY KEY,
+	timezone VARCHAR(64)
+);
+
+
+CREATE TABLE IF NOT EXISTS guild_members (
+	guild_id BIGINT NOT NULL,
+	user_id BIGINT NOT NULL,
+	discord_username VARCHAR(45),
+	PRIMARY KEY (guild_id, user_id)
+);
+
+
+CREATE TABLE IF NOT EXISTS artist_crowns (
+	guild_id BIGINT NOT NULL,
+	artist_key VARCHAR(512) NOT NULL,
+	artist VARCHAR(512),
+	user_id BIGINT,
+	discord_username VARCHAR(45),
+	playcount BIGINT,
+	claimed_playcount BIGINT,
+	claimed_at BIGINT,
+	PRIMARY KEY (guild_id, artist_key)
+);
+
+
+CREATE TABLE IF NOT EXISTS milestone_settings (
+	discord_username VARCHAR(45) PRIMARY KEY,
+	user_id BIGINT,
+	announce VARCHAR(16),
+	channel_id BIGINT,
+	last_total BIGINT
+);
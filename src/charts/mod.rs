@@ -0,0 +1,472 @@
+use crate::discord::bot::{Context, MljboardUser};
+use crate::history::{fetch_art, history_in_range, ArtSource, BackendError, HistoryEntry};
+use crate::lfm::json::{largest_image, LastFMChartItem};
+use crate::normalize::{artist_key, credits, normalize, split_artists};
+use crate::range::StatsRange;
+use chrono_tz::Tz;
+use reqwest::Client;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, poise::ChoiceParameter)]
+pub enum ChartKind {
+    Artists,
+    Albums,
+    Tracks,
+}
+
+/// One ranked row of a chart, the same shape whichever backend it came from.
+#[derive(Clone, Debug)]
+pub struct ChartEntry {
+    pub name: String,
+    /// Who made it, for albums and tracks.
+    pub artist: Option<String>,
+    pub count: u64,
+    pub url: Option<String>,
+    pub art: Option<ArtSource>,
+}
+
+/// Counts up a chart from individual scrobbles, for ranges a backend has no chart for.
+/// Every artist on a scrobble is counted in an artist chart, featured ones too, like Maloja does.
+pub fn chart_from_history(entries: Vec<HistoryEntry>, kind: ChartKind) -> Vec<ChartEntry> {
+    let mut counts: HashMap<(String, String), ChartEntry> = HashMap::new();
+    for entry in entries {
+        let rows: Vec<(String, Option<String>)> = match kind {
+            ChartKind::Artists => split_artists(&entry.artist)
+                .into_iter()
+                .map(|artist| (artist, None))
+                .collect(),
+            ChartKind::Albums => match entry.album {
+                Some(album) => vec![(album, Some(entry.artist))],
+                None => continue,
+            },
+            ChartKind::Tracks => vec![(entry.track, Some(entry.artist))],
+        };
+        for (name, artist) in rows {
+            let key = (
+                normalize(&name),
+                artist.as_deref().map(artist_key).unwrap_or_default(),
+            );
+            counts
+                .entry(key)
+                .or_insert(ChartEntry {
+                    name,
+                    artist,
+                    count: 0,
+                    url: None,
+                    art: match kind {
+                        ChartKind::Albums => entry.art.clone(),
+                        _ => None,
+                    },
+                })
+                .count += 1;
+        }
+    }
+    let mut chart: Vec<ChartEntry> = counts.into_values().collect();
+    chart.sort_by(|a, b| b.count.cmp(&a.count).then(a.name.cmp(&b.name)));
+    chart
+}
+
+fn chart_item_entries(items: Vec<LastFMChartItem>) -> Vec<ChartEntry> {
+    items
+        .into_iter()
+        .map(|item| ChartEntry {
+            name: item.name,
+            artist: Some(item.artist.text),
+            count: item.playcount.parse::<u64>().unwrap_or(0),
+            url: Some(item.url).filter(|x| !x.is_empty()),
+            art: None,
+        })
+        .collect()
+}
+
+/// The user's top `limit` artists, albums or tracks in `range`, most played first.
+pub async fn get_chart(
+    client: Client,
+    ctx: Context<'_>,
+    user: &MljboardUser,
+    kind: ChartKind,
+    range: &StatsRange,
+    tz: Option<Tz>,
+    limit: usize,
+) -> Result<Vec<ChartEntry>, BackendError> {
+    let mut entries: Vec<ChartEntry> = match user {
+        MljboardUser::MalojaUser(creds) => match range.maloja(tz) {
+            Some(maloja_range) => match kind {
+                ChartKind::Artists => {
+                    mljcl::charts::charts_artists_async(maloja_range, creds.clone(), client)
+                        .await
+                        .map(|x| {
+                            x.artists
+                                .into_iter()
+                                .map(|(artist, count)| ChartEntry {
+                                    name: artist.name,
+                                    artist: None,
+                                    count,
+                                    url: None,
+                                    art: None,
+                                })
+                                .collect()
+                        })
+                }
+                ChartKind::Albums => {
+                    mljcl::charts::charts_albums_async(maloja_range, None, creds.clone(), client)
+                        .await
+                        .map(|x| {
+                            x.albums
+                                .into_iter()
+                                .map(|(album, count)| ChartEntry {
+                                    artist: Some(
+                                        album
+                                            .artists
+                                            .iter()
+                                            .map(|artist| artist.name.clone())
+                                            .collect::<Vec<String>>()
+                                            .join(", "),
+                                    ),
+                                    art: Some(ArtSource::MalojaAlbum(album.id.clone())),
+                                    name: album.name,
+                                    count,
+                                    url: None,
+                                })
+                                .collect()
+                        })
+                }
+                ChartKind::Tracks => {
+                    mljcl::charts::charts_tracks_async(maloja_range, None, creds.clone(), client)
+                        .await
+                        .map(|x| {
+                            x.tracks
+                                .into_iter()
+                                .map(|(track, count)| ChartEntry {
+                                    artist: Some(
+                                        track
+                                            .artists
+                                            .iter()
+                                            .map(|artist| artist.name.clone())
+                                            .collect::<Vec<String>>()
+                                            .join(", "),
+                                    ),
+                                    name: track.title,
+                                    count,
+                                    url: None,
+                                    art: None,
+                                })
+                                .collect()
+                        })
+                }
+            }
+            .map_err(|_| BackendError::Maloja)?,
+            None => chart_from_history(history_in_range(client, ctx, user, range, tz).await?, kind),
+        },
+        MljboardUser::LastFMUser(lfm) => {
+            let lastfm = ctx.data().lastfm_for(lfm);
+            let bounds = range.bounds(tz);
+            match (bounds.start, bounds.end) {
+                (Some(from), Some(to)) => match kind {
+                    ChartKind::Artists => lastfm
+                        .user_get_weekly_artist_chart(lfm.username.clone(), from, to)
+                        .await
+                        .map(|artists| {
+                            artists
+                                .into_iter()
+                                .map(|artist| ChartEntry {
+                                    name: artist.name,
+                                    artist: None,
+                                    count: artist.playcount.parse::<u64>().unwrap_or(0),
+                                    url: Some(artist.url).filter(|x| !x.is_empty()),
+                                    art: None,
+                                })
+                                .collect()
+                        }),
+                    ChartKind::Albums => lastfm
+                        .user_get_weekly_album_chart(lfm.username.clone(), from, to)
+                        .await
+                        .map(chart_item_entries),
+                    ChartKind::Tracks => lastfm
+                        .user_get_weekly_track_chart(lfm.username.clone(), from, to)
+                        .await
+                        .map(chart_item_entries),
+                },
+                _ => match kind {
+                    ChartKind::Artists => lastfm
+                        .user_get_top_artists(lfm.username.clone(), "overall".to_string(), limit)
+                        .await
+                        .map(|artists| {
+                            artists
+                                .into_iter()
+                                .map(|artist| ChartEntry {
+                                    name: artist.name,
+                                    artist: None,
+                                    count: artist.playcount.parse::<u64>().unwrap_or(0),
+                                    url: Some(artist.url).filter(|x| !x.is_empty()),
+                                    art: None,
+                                })
+                                .collect()
+                        }),
+                    ChartKind::Albums => lastfm
+                        .user_get_top_albums(lfm.username.clone(), "overall".to_string(), limit)
+                        .await
+                        .map(|albums| {
+                            albums
+                                .into_iter()
+                                .map(|album| ChartEntry {
+                                    art: largest_image(&album.image).map(ArtSource::Url),
+                                    name: album.name,
+                                    artist: Some(album.artist.name),
+                                    count: album.playcount.parse::<u64>().unwrap_or(0),
+                                    url: Some(album.url).filter(|x| !x.is_empty()),
+                                })
+                                .collect()
+                        }),
+                    ChartKind::Tracks => lastfm
+                        .user_get_top_tracks(lfm.username.clone(), "overall".to_string(), limit)
+                        .await
+                        .map(|tracks| {
+                            tracks
+                                .into_iter()
+                                .map(|track| ChartEntry {
+                                    name: track.name,
+                                    artist: Some(track.artist.name),
+                                    count: track.playcount.parse::<u64>().unwrap_or(0),
+                                    url: Some(track.url).filter(|x| !x.is_empty()),
+                                    art: None,
+                                })
+                                .collect()
+                        }),
+                },
+            }
+            .map_err(BackendError::LastFM)?
+        }
+        MljboardUser::ListenBrainzUser(lb) => {
+            let listenbrainz = &ctx.data().listenbrainz;
+            match range.listenbrainz(tz) {
+                Some(stats_range) => match kind {
+                    ChartKind::Artists => listenbrainz
+                        .top_artists(lb.username.clone(), stats_range, limit, 0)
+                        .await
+                        .map(|stats| {
+                            stats
+                                .artists
+                                .into_iter()
+                                .map(|artist| ChartEntry {
+                                    name: artist.artist_name,
+                                    artist: None,
+                                    count: artist.listen_count,
+                                    url: None,
+                                    art: None,
+                                })
+                                .collect()
+                        }),
+                    ChartKind::Albums => listenbrainz
+                        .top_releases(lb.username.clone(), stats_range, limit)
+                        .await
+                        .map(|releases| {
+                            releases
+                                .into_iter()
+                                .map(|release| ChartEntry {
+                                    art: release.art_url().map(ArtSource::Url),
+                                    name: release.release_name,
+                                    artist: Some(release.artist_name),
+                                    count: release.listen_count,
+                                    url: None,
+                                })
+                                .collect()
+                        }),
+                    ChartKind::Tracks => listenbrainz
+                        .top_recordings(lb.username.clone(), stats_range, limit)
+                        .await
+                        .map(|recordings| {
+                            recordings
+                                .into_iter()
+                                .map(|recording| ChartEntry {
+                                    name: recording.track_name,
+                                    artist: Some(recording.artist_name),
+                                    count: recording.listen_count,
+                                    url: None,
+                                    art: None,
+                                })
+                                .collect()
+                        }),
+                }
+                .map_err(BackendError::ListenBrainz)?,
+                None => {
+                    chart_from_history(history_in_range(client, ctx, user, range, tz).await?, kind)
+                }
+            }
+        }
+    };
+    entries.truncate(limit);
+    Ok(entries)
+}
+
+/// Downloads the art for an album chart entry, looking it up on Last.FM when the chart didn't come with any.
+pub async fn chart_art(
+    client: Client,
+    ctx: Context<'_>,
+    user: &MljboardUser,
+    entry: &ChartEntry,
+) -> Option<Vec<u8>> {
+    let art = match (&entry.art, user, &entry.artist) {
+        (Some(art), _, _) => art.clone(),
+        (None, MljboardUser::LastFMUser(lfm), Some(artist)) => ctx
+            .data()
+            .lastfm_for(lfm)
+            .album_get_info(artist.clone(), entry.name.clone())
+            .await
+            .ok()
+            .and_then(|album| largest_image(&album.image))
+            .map(ArtSource::Url)?,
+        _ => return None,
+    };
+    fetch_art(client, user, &art).await
+}
+
+/// Albums or tracks in `chart` by `artist` called `name`, compared normalized. When nothing is called exactly that,
+/// anything whose name contains it, so "Wish You Were Here" finds "Wish You Were Here - 2011 Remaster".
+/// `comma_joined` is passed on to `credits`, for charts from Maloja.
+pub fn find_entries(
+    chart: &[ChartEntry],
+    artist: &str,
+    name: &str,
+    comma_joined: bool,
+) -> Vec<ChartEntry> {
+    let name = normalize(name);
+    let by_artist: Vec<&ChartEntry> = chart
+        .iter()
+        .filter(|entry| {
+            entry
+                .artist
+                .as_ref()
+                .is_some_and(|credit| credits(credit, artist, comma_joined))
+        })
+        .collect();
+    let exact: Vec<ChartEntry> = by_artist
+        .iter()
+        .filter(|entry| normalize(&entry.name) == name)
+        .map(|entry| (*entry).clone())
+        .collect();
+    if !exact.is_empty() {
+        return exact;
+    }
+    by_artist
+        .into_iter()
+        .filter(|entry| normalize(&entry.name).contains(&name))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scrobble(artist: &str, track: &str, album: Option<&str>) -> HistoryEntry {
+        HistoryEntry {
+            timestamp: Some(0),
+            artist: artist.to_string(),
+            track: track.to_string(),
+            album: album.map(|x| x.to_string()),
+            url: None,
+            art: None,
+            duration: None,
+        }
+    }
+
+    fn counts(chart: &[ChartEntry]) -> Vec<(&str, u64)> {
+        chart
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.count))
+            .collect()
+    }
+
+    #[test]
+    fn artist_charts_count_featured_artists() {
+        let chart = chart_from_history(
+            vec![
+                scrobble("Daft Punk", "One More Time", None),
+                scrobble("Daft Punk feat. Pharrell Williams", "Get Lucky", None),
+                scrobble("daft punk", "Digital Love", None),
+            ],
+            ChartKind::Artists,
+        );
+        assert_eq!(
+            counts(&chart),
+            vec![("Daft Punk", 3), ("Pharrell Williams", 1)]
+        );
+    }
+
+    #[test]
+    fn same_name_by_different_artists_is_kept_apart() {
+        let chart = chart_from_history(
+            vec![
+                scrobble("Radiohead", "Creep", None),
+                scrobble("The Radiohead", "creep", None),
+                scrobble("TLC", "Creep", None),
+            ],
+            ChartKind::Tracks,
+        );
+        assert_eq!(counts(&chart), vec![("Creep", 2), ("Creep", 1)]);
+        assert_eq!(chart[1].artist.as_deref(), Some("TLC"));
+    }
+
+    #[test]
+    fn album_charts_skip_scrobbles_without_albums() {
+        let chart = chart_from_history(
+            vec![
+                scrobble("Björk", "Joga", Some("Homogenic")),
+                scrobble("Björk", "Bachelorette", Some("Homogenic")),
+                scrobble("Björk", "Human Behaviour", Some("Debut")),
+                scrobble("Björk", "Jóga", None),
+            ],
+            ChartKind::Albums,
+        );
+        assert_eq!(counts(&chart), vec![("Homogenic", 2), ("Debut", 1)]);
+    }
+
+    fn entry(name: &str, artist: &str, count: u64) -> ChartEntry {
+        ChartEntry {
+            name: name.to_string(),
+            artist: Some(artist.to_string()),
+            count,
+            url: None,
+            art: None,
+        }
+    }
+
+    #[test]
+    fn exact_names_win_over_partial_ones() {
+        let chart = vec![
+            entry("Wish You Were Here", "Pink Floyd", 10),
+            entry("Wish You Were Here - 2011 Remaster", "Pink Floyd", 5),
+        ];
+        let found = find_entries(&chart, "pink floyd", "wish you were here", false);
+        assert_eq!(counts(&found), vec![("Wish You Were Here", 10)]);
+    }
+
+    #[test]
+    fn partial_names_are_found_when_nothing_matches_exactly() {
+        let chart = vec![
+            entry("Wish You Were Here - 2011 Remaster", "Pink Floyd", 5),
+            entry("Wish You Were Here (Live)", "Pink Floyd", 2),
+            entry("Wish You Were Here", "Incubus", 7),
+        ];
+        let found = find_entries(&chart, "Pink Floyd", "Wish You Were Here", false);
+        assert_eq!(
+            counts(&found),
+            vec![
+                ("Wish You Were Here - 2011 Remaster", 5),
+                ("Wish You Were Here (Live)", 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn featured_credits_count_for_the_artist() {
+        let chart = vec![
+            entry("Get Lucky", "Daft Punk feat. Pharrell Williams", 4),
+            entry("Happy", "Pharrell Williams", 9),
+        ];
+        let found = find_entries(&chart, "Daft Punk", "get lucky", false);
+        assert_eq!(counts(&found), vec![("Get Lucky", 4)]);
+        assert!(find_entries(&chart, "Daft Punk", "Happy", false).is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, QueryBuilder};
 
 pub async fn start_db(postgres_url: String) -> Result<PgPool, sqlx::Error> {
     PgPool::connect(&postgres_url).await
@@ -20,6 +20,86 @@ pub struct DiscordPairingCodeUser {
 pub struct DiscordLastFMUser {
     pub discord_username: Option<String>,
     pub lastfm_username: Option<String>,
+    /// Set once the user has proven they own the account through Last.FM's web authentication.
+    pub session_key: Option<String>,
+    /// API base URL of a Last.FM-compatible service like Libre.fm, `None` for Last.FM itself.
+    pub service: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct DiscordListenBrainzUser {
+    pub discord_username: Option<String>,
+    pub listenbrainz_username: Option<String>,
+}
+
+#[derive(sqlx::FromRow, Clone, Debug)]
+pub struct LastFMScrobble {
+    /// Empty for Last.FM itself, otherwise the API base URL of the service the scrobble came from.
+    pub service: Option<String>,
+    pub lastfm_username: Option<String>,
+    pub scrobbled_at: Option<i64>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub track: Option<String>,
+    pub url: Option<String>,
+}
+
+#[derive(sqlx::FromRow, Clone, Debug)]
+pub struct LastFMSyncStatus {
+    pub service: Option<String>,
+    pub lastfm_username: Option<String>,
+    pub last_synced: Option<i64>,
+    pub last_attempt: Option<i64>,
+    pub failures: Option<i32>,
+    pub last_error: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct PrivacySetting {
+    pub discord_username: Option<String>,
+    pub public: Option<bool>,
+}
+
+/// A member seen using the bot in a guild, which is who guild leaderboards are made of.
+#[derive(sqlx::FromRow)]
+pub struct GuildMember {
+    pub guild_id: Option<i64>,
+    pub user_id: Option<i64>,
+    pub discord_username: Option<String>,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct ArtistCrown {
+    pub guild_id: Option<i64>,
+    /// The artist name as it's compared, `artist` is how it's shown.
+    pub artist_key: Option<String>,
+    pub artist: Option<String>,
+    pub user_id: Option<i64>,
+    pub discord_username: Option<String>,
+    /// The holder's count the last time it was seen.
+    pub playcount: Option<i64>,
+    pub claimed_playcount: Option<i64>,
+    pub claimed_at: Option<i64>,
+}
+
+#[derive(sqlx::FromRow)]
+pub struct UserTimezone {
+    pub discord_username: Option<String>,
+    /// An IANA name like `America/Los_Angeles`.
+    pub timezone: Option<String>,
+}
+
+/// Where someone wants to hear about crossing a round number of scrobbles.
+#[derive(sqlx::FromRow)]
+pub struct MilestoneSetting {
+    pub discord_username: Option<String>,
+    /// Who to DM or mention, so milestones can be announced outside of a command.
+    pub user_id: Option<i64>,
+    /// `dm`, `channel` or `off`.
+    pub announce: Option<String>,
+    pub channel_id: Option<i64>,
+    /// The total the last time it was seen, to tell when a milestone was crossed.
+    pub last_total: Option<i64>,
 }
 
 pub async fn get_websites(pool: &PgPool, formatted_user: String) -> Vec<DiscordWebsiteUser> {
@@ -64,6 +144,213 @@ pub async fn get_lastfm_username(pool: &PgPool, formatted_user: String) -> Vec<D
     .expect("Failed to query DB for Last.FM username")
 }
 
+pub async fn get_listenbrainz_username(
+    pool: &PgPool,
+    formatted_user: String,
+) -> Vec<DiscordListenBrainzUser> {
+    sqlx::query_as::<_, DiscordListenBrainzUser>(
+        r#"
+        SELECT * FROM listenbrainz_usernames
+        WHERE discord_username = $1
+        "#,
+    )
+    .bind(formatted_user)
+    .fetch_all(pool)
+    .await
+    .expect("Failed to query DB for ListenBrainz username")
+}
+
+pub async fn get_privacy_setting(pool: &PgPool, formatted_user: String) -> Vec<PrivacySetting> {
+    sqlx::query_as::<_, PrivacySetting>(
+        r#"
+        SELECT * FROM privacy_settings
+        WHERE discord_username = $1
+        "#,
+    )
+    .bind(formatted_user)
+    .fetch_all(pool)
+    .await
+    .expect("Failed to query DB for privacy setting")
+}
+
+pub async fn get_milestone_setting(pool: &PgPool, formatted_user: String) -> Vec<MilestoneSetting> {
+    sqlx::query_as::<_, MilestoneSetting>(
+        r#"
+        SELECT * FROM milestone_settings
+        WHERE discord_username = $1
+        "#,
+    )
+    .bind(formatted_user)
+    .fetch_all(pool)
+    .await
+    .expect("Failed to query DB for milestone setting")
+}
+
+pub async fn get_user_timezone(pool: &PgPool, formatted_user: String) -> Vec<UserTimezone> {
+    sqlx::query_as::<_, UserTimezone>(
+        r#"
+        SELECT * FROM user_timezones
+        WHERE discord_username = $1
+        "#,
+    )
+    .bind(formatted_user)
+    .fetch_all(pool)
+    .await
+    .expect("Failed to query DB for timezone")
+}
+
+pub async fn get_guild_members(pool: &PgPool, guild_id: i64) -> Vec<GuildMember> {
+    sqlx::query_as::<_, GuildMember>(
+        r#"
+        SELECT * FROM guild_members
+        WHERE guild_id = $1
+        "#,
+    )
+    .bind(guild_id)
+    .fetch_all(pool)
+    .await
+    .expect("Failed to query DB for guild members")
+}
+
+pub async fn get_crown(pool: &PgPool, guild_id: i64, artist_key: String) -> Vec<ArtistCrown> {
+    sqlx::query_as::<_, ArtistCrown>(
+        r#"
+        SELECT * FROM artist_crowns
+        WHERE guild_id = $1 AND artist_key = $2
+        "#,
+    )
+    .bind(guild_id)
+    .bind(artist_key)
+    .fetch_all(pool)
+    .await
+    .expect("Failed to query DB for crown")
+}
+
+/// Every crown `user_id` holds in `guild_id`, biggest first.
+pub async fn get_user_crowns(pool: &PgPool, guild_id: i64, user_id: i64) -> Vec<ArtistCrown> {
+    sqlx::query_as::<_, ArtistCrown>(
+        r#"
+        SELECT * FROM artist_crowns
+        WHERE guild_id = $1 AND user_id = $2
+        ORDER BY playcount DESC
+        "#,
+    )
+    .bind(guild_id)
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .expect("Failed to query DB for crowns")
+}
+
+pub async fn get_all_lastfm_usernames(pool: &PgPool) -> Vec<DiscordLastFMUser> {
+    sqlx::query_as::<_, DiscordLastFMUser>(
+        r#"
+        SELECT * FROM lastfm_usernames
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .expect("Failed to query DB for Last.FM usernames")
+}
+
+pub async fn get_lastfm_sync_status(
+    pool: &PgPool,
+    service: String,
+    username: String,
+) -> Vec<LastFMSyncStatus> {
+    sqlx::query_as::<_, LastFMSyncStatus>(
+        r#"
+        SELECT * FROM lastfm_sync_status
+        WHERE service = $1 AND lastfm_username = $2
+        "#,
+    )
+    .bind(service)
+    .bind(username)
+    .fetch_all(pool)
+    .await
+    .expect("Failed to query DB for Last.FM sync status")
+}
+
+pub async fn count_lastfm_scrobbles(pool: &PgPool, service: String, username: String) -> i64 {
+    sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT COUNT(*) FROM lastfm_scrobbles
+        WHERE service = $1 AND lastfm_username = $2
+        "#,
+    )
+    .bind(service)
+    .bind(username)
+    .fetch_one(pool)
+    .await
+    .expect("Failed to count cached scrobbles")
+}
+
+/// The `n`th cached scrobble of `username`, counting from the oldest at 0.
+pub async fn get_nth_lastfm_scrobble(
+    pool: &PgPool,
+    service: String,
+    username: String,
+    n: i64,
+) -> Vec<LastFMScrobble> {
+    sqlx::query_as::<_, LastFMScrobble>(
+        r#"
+        SELECT * FROM lastfm_scrobbles
+        WHERE service = $1 AND lastfm_username = $2
+        ORDER BY scrobbled_at ASC
+        OFFSET $3 LIMIT 1
+        "#,
+    )
+    .bind(service)
+    .bind(username)
+    .bind(n)
+    .fetch_all(pool)
+    .await
+    .expect("Failed to query DB for cached scrobble")
+}
+
+pub async fn get_latest_lastfm_scrobble_time(
+    pool: &PgPool,
+    service: String,
+    username: String,
+) -> Option<i64> {
+    sqlx::query_scalar::<_, Option<i64>>(
+        r#"
+        SELECT MAX(scrobbled_at) FROM lastfm_scrobbles
+        WHERE service = $1 AND lastfm_username = $2
+        "#,
+    )
+    .bind(service)
+    .bind(username)
+    .fetch_one(pool)
+    .await
+    .expect("Failed to query DB for latest cached scrobble")
+}
+
+pub async fn get_lastfm_scrobbles(
+    pool: &PgPool,
+    service: String,
+    username: String,
+    start: Option<i64>,
+    end: Option<i64>,
+) -> Vec<LastFMScrobble> {
+    sqlx::query_as::<_, LastFMScrobble>(
+        r#"
+        SELECT * FROM lastfm_scrobbles
+        WHERE service = $1 AND lastfm_username = $2
+        AND ($3::BIGINT IS NULL OR scrobbled_at >= $3)
+        AND ($4::BIGINT IS NULL OR scrobbled_at <= $4)
+        ORDER BY scrobbled_at DESC
+        "#,
+    )
+    .bind(service)
+    .bind(username)
+    .bind(start)
+    .bind(end)
+    .fetch_all(pool)
+    .await
+    .expect("Failed to query DB for cached scrobbles")
+}
+
 pub async fn insert_website(pool: &PgPool, formatted_user: String, website: String) {
     let _ = sqlx::query(
         r#"
@@ -92,20 +379,262 @@ pub async fn insert_discord_pairing_code(pool: &PgPool, formatted_user: String,
     .expect("Failed to add pairing code to DB");
 }
 
-pub async fn insert_lastfm_user(pool: &PgPool, formatted_user: String, username: String) {
+pub async fn insert_lastfm_user(
+    pool: &PgPool,
+    formatted_user: String,
+    username: String,
+    service: Option<String>,
+) {
     let _ = sqlx::query(
         r#"
-        INSERT INTO lastfm_usernames
-        VALUES ( $1, $2 )
+        INSERT INTO lastfm_usernames (discord_username, lastfm_username, service)
+        VALUES ( $1, $2, $3 )
         "#,
     )
     .bind(formatted_user)
     .bind(username)
+    .bind(service)
     .execute(pool)
     .await
     .expect("Failed to add Last.FM user to DB");
 }
 
+pub async fn insert_lastfm_scrobbles(pool: &PgPool, scrobbles: Vec<LastFMScrobble>) {
+    // postgres caps a statement at 65535 bind parameters, so insert in chunks
+    for chunk in scrobbles.chunks(5000) {
+        let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            "INSERT INTO lastfm_scrobbles (service, lastfm_username, scrobbled_at, artist, album, track, url) ",
+        );
+        builder.push_values(chunk, |mut row, scrobble| {
+            row.push_bind(scrobble.service.clone().unwrap_or_default())
+                .push_bind(scrobble.lastfm_username.clone())
+                .push_bind(scrobble.scrobbled_at)
+                .push_bind(scrobble.artist.clone())
+                .push_bind(scrobble.album.clone())
+                .push_bind(scrobble.track.clone())
+                .push_bind(scrobble.url.clone());
+        });
+        // the background worker and a command can sync the same user at once
+        builder.push(" ON CONFLICT DO NOTHING");
+        builder
+            .build()
+            .execute(pool)
+            .await
+            .expect("Failed to add cached scrobbles to DB");
+    }
+}
+
+pub async fn insert_listenbrainz_user(pool: &PgPool, formatted_user: String, username: String) {
+    let _ = sqlx::query(
+        r#"
+        INSERT INTO listenbrainz_usernames
+        VALUES ( $1, $2 )
+        "#,
+    )
+    .bind(formatted_user)
+    .bind(username)
+    .execute(pool)
+    .await
+    .expect("Failed to add ListenBrainz user to DB");
+}
+
+pub async fn set_privacy_setting(pool: &PgPool, formatted_user: String, public: bool) {
+    let _ = sqlx::query(
+        r#"
+        INSERT INTO privacy_settings
+        VALUES ( $1, $2 )
+        ON CONFLICT (discord_username) DO UPDATE
+        SET public = $2
+        "#,
+    )
+    .bind(formatted_user)
+    .bind(public)
+    .execute(pool)
+    .await
+    .expect("Failed to set privacy setting");
+}
+
+pub async fn set_milestone_setting(
+    pool: &PgPool,
+    formatted_user: String,
+    user_id: i64,
+    announce: String,
+    channel_id: Option<i64>,
+) {
+    let _ = sqlx::query(
+        r#"
+        INSERT INTO milestone_settings (discord_username, user_id, announce, channel_id)
+        VALUES ( $1, $2, $3, $4 )
+        ON CONFLICT (discord_username) DO UPDATE
+        SET user_id = $2, announce = $3, channel_id = $4
+        "#,
+    )
+    .bind(formatted_user)
+    .bind(user_id)
+    .bind(announce)
+    .bind(channel_id)
+    .execute(pool)
+    .await
+    .expect("Failed to set milestone setting");
+}
+
+pub async fn set_milestone_total(pool: &PgPool, formatted_user: String, total: i64) {
+    let _ = sqlx::query(
+        r#"
+        UPDATE milestone_settings
+        SET last_total = $2
+        WHERE discord_username = $1
+        "#,
+    )
+    .bind(formatted_user)
+    .bind(total)
+    .execute(pool)
+    .await
+    .expect("Failed to set milestone total");
+}
+
+pub async fn record_guild_member(
+    pool: &PgPool,
+    guild_id: i64,
+    user_id: i64,
+    formatted_user: String,
+) {
+    let _ = sqlx::query(
+        r#"
+        INSERT INTO guild_members
+        VALUES ( $1, $2, $3 )
+        ON CONFLICT (guild_id, user_id) DO UPDATE
+        SET discord_username = $3
+        "#,
+    )
+    .bind(guild_id)
+    .bind(user_id)
+    .bind(formatted_user)
+    .execute(pool)
+    .await
+    .expect("Failed to record guild member");
+}
+
+/// Gives the crown for `artist_key` in `guild_id` to a new holder, at `playcount` plays.
+pub async fn set_crown(pool: &PgPool, crown: ArtistCrown) {
+    let _ = sqlx::query(
+        r#"
+        INSERT INTO artist_crowns
+        VALUES ( $1, $2, $3, $4, $5, $6, $6, $7 )
+        ON CONFLICT (guild_id, artist_key) DO UPDATE
+        SET artist = $3, user_id = $4, discord_username = $5, playcount = $6, claimed_playcount = $6, claimed_at = $7
+        "#,
+    )
+    .bind(crown.guild_id)
+    .bind(crown.artist_key)
+    .bind(crown.artist)
+    .bind(crown.user_id)
+    .bind(crown.discord_username)
+    .bind(crown.playcount)
+    .bind(crown.claimed_at)
+    .execute(pool)
+    .await
+    .expect("Failed to set crown");
+}
+
+pub async fn update_crown_playcount(
+    pool: &PgPool,
+    guild_id: i64,
+    artist_key: String,
+    playcount: i64,
+) {
+    let _ = sqlx::query(
+        r#"
+        UPDATE artist_crowns
+        SET playcount = $3
+        WHERE guild_id = $1 AND artist_key = $2
+        "#,
+    )
+    .bind(guild_id)
+    .bind(artist_key)
+    .bind(playcount)
+    .execute(pool)
+    .await
+    .expect("Failed to update crown playcount");
+}
+
+pub async fn set_user_timezone(pool: &PgPool, formatted_user: String, timezone: String) {
+    let _ = sqlx::query(
+        r#"
+        INSERT INTO user_timezones
+        VALUES ( $1, $2 )
+        ON CONFLICT (discord_username) DO UPDATE
+        SET timezone = $2
+        "#,
+    )
+    .bind(formatted_user)
+    .bind(timezone)
+    .execute(pool)
+    .await
+    .expect("Failed to set timezone");
+}
+
+pub async fn set_lastfm_session_key(pool: &PgPool, formatted_user: String, session_key: String) {
+    let _ = sqlx::query(
+        r#"
+        UPDATE lastfm_usernames
+        SET session_key = $2
+        WHERE discord_username = $1
+        "#,
+    )
+    .bind(formatted_user)
+    .bind(session_key)
+    .execute(pool)
+    .await
+    .expect("Failed to add Last.FM session key to DB");
+}
+
+pub async fn record_lastfm_sync_success(
+    pool: &PgPool,
+    service: String,
+    username: String,
+    now: i64,
+) {
+    let _ = sqlx::query(
+        r#"
+        INSERT INTO lastfm_sync_status (service, lastfm_username, last_synced, last_attempt, failures, last_error)
+        VALUES ( $1, $2, $3, $3, 0, NULL )
+        ON CONFLICT (service, lastfm_username) DO UPDATE
+        SET last_synced = $3, last_attempt = $3, failures = 0, last_error = NULL
+        "#,
+    )
+    .bind(service)
+    .bind(username)
+    .bind(now)
+    .execute(pool)
+    .await
+    .expect("Failed to record Last.FM sync");
+}
+
+pub async fn record_lastfm_sync_failure(
+    pool: &PgPool,
+    service: String,
+    username: String,
+    now: i64,
+    error: String,
+) {
+    let _ = sqlx::query(
+        r#"
+        INSERT INTO lastfm_sync_status (service, lastfm_username, last_synced, last_attempt, failures, last_error)
+        VALUES ( $1, $2, NULL, $3, 1, $4 )
+        ON CONFLICT (service, lastfm_username) DO UPDATE
+        SET last_attempt = $3, failures = lastfm_sync_status.failures + 1, last_error = $4
+        "#,
+    )
+    .bind(service)
+    .bind(username)
+    .bind(now)
+    .bind(error)
+    .execute(pool)
+    .await
+    .expect("Failed to record Last.FM sync failure");
+}
+
 pub async fn delete_discord_pairing_code(pool: &PgPool, formatted_user: String) -> u64 {
     sqlx::query(
         r#"
@@ -147,3 +676,17 @@ pub async fn delete_lastfm_user(pool: &PgPool, formatted_user: String) -> u64 {
     .expect("Failed to delete Last.FM user")
     .rows_affected()
 }
+
+pub async fn delete_listenbrainz_user(pool: &PgPool, formatted_user: String) -> u64 {
+    sqlx::query(
+        r#"
+        DELETE FROM listenbrainz_usernames
+        WHERE discord_username = $1
+        "#,
+    )
+    .bind(formatted_user)
+    .execute(pool)
+    .await
+    .expect("Failed to delete ListenBrainz user")
+    .rows_affected()
+}
@@ -0,0 +1,187 @@
+use super::bot::{format_user, Context};
+use crate::charts::{get_chart, ChartEntry, ChartKind};
+use crate::normalize::normalize;
+use crate::range::StatsRange;
+use poise::serenity_prelude::{AutocompleteChoice, ResolvedValue};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a library fetched for suggestions is reused before it's fetched again.
+pub const LIBRARY_CACHE_TTL: Duration = Duration::from_secs(600);
+/// How far down each library suggestions come from.
+pub const LIBRARY_CACHE_SIZE: usize = 1000;
+/// Discord won't show more suggestions than this.
+pub const MAX_SUGGESTIONS: usize = 25;
+
+/// Every member's artists, albums and tracks as last fetched, so suggestions keep up with typing.
+#[derive(Debug, Default)]
+pub struct LibraryCache {
+    libraries: Mutex<HashMap<(String, ChartKind), (Instant, Vec<ChartEntry>)>>,
+}
+
+impl LibraryCache {
+    fn get(&self, formatted_user: &str, kind: ChartKind) -> Option<Vec<ChartEntry>> {
+        let libraries = self.libraries.lock().unwrap();
+        libraries
+            .get(&(formatted_user.to_string(), kind))
+            .filter(|(fetched_at, _)| fetched_at.elapsed() < LIBRARY_CACHE_TTL)
+            .map(|(_, library)| library.clone())
+    }
+
+    fn insert(&self, formatted_user: String, kind: ChartKind, library: Vec<ChartEntry>) {
+        let mut libraries = self.libraries.lock().unwrap();
+        libraries.retain(|_, (fetched_at, _)| fetched_at.elapsed() < LIBRARY_CACHE_TTL);
+        libraries.insert((formatted_user, kind), (Instant::now(), library));
+    }
+}
+
+/// The caller's own library, from the cache when it's fresh.
+async fn caller_library(ctx: Context<'_>, kind: ChartKind) -> Vec<ChartEntry> {
+    let formatted_user = format_user(ctx.author().clone());
+    let cache = &ctx.data().library_cache;
+    if let Some(library) = cache.get(&formatted_user, kind) {
+        return library;
+    }
+    let Some(user) = ctx
+        .data()
+        .handle_creds_quietly(formatted_user.clone())
+        .await
+    else {
+        return vec![];
+    };
+    match get_chart(
+        ctx.data().reqwest_client.clone(),
+        ctx,
+        &user,
+        kind,
+        &StatsRange::AllTime,
+        None,
+        LIBRARY_CACHE_SIZE,
+    )
+    .await
+    {
+        Ok(library) => {
+            cache.insert(formatted_user, kind, library.clone());
+            library
+        }
+        Err(err) => {
+            log::error!("Error getting library for suggestions: {}", err);
+            vec![]
+        }
+    }
+}
+
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.iter().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, y) in b.iter().enumerate() {
+            current.push(
+                (previous[j] + usize::from(x != y))
+                    .min(previous[j + 1] + 1)
+                    .min(current[j] + 1),
+            );
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// How well `name` matches what's been typed so far, lower is better, `None` if it doesn't at all.
+/// Besides plain prefixes and substrings, letters typed in order ("rdhd" for Radiohead)
+/// and a typo or two against the start of the name count too.
+pub fn match_score(typed: &str, name: &str) -> Option<usize> {
+    let (typed, name) = (normalize(typed), normalize(name));
+    if typed.is_empty() || name.starts_with(&typed) {
+        return Some(0);
+    }
+    if name.split_whitespace().any(|word| word.starts_with(&typed)) {
+        return Some(1);
+    }
+    if name.contains(&typed) {
+        return Some(2);
+    }
+    let mut letters = name.chars();
+    if typed.chars().all(|c| letters.any(|x| x == c)) {
+        return Some(3);
+    }
+    let typed: Vec<char> = typed.chars().collect();
+    let start: Vec<char> = name.chars().take(typed.len()).collect();
+    let distance = edit_distance(&typed, &start);
+    match distance <= (typed.len() / 4).max(1) {
+        true => Some(4 + distance),
+        false => None,
+    }
+}
+
+/// What's already been filled in for another option of the same command.
+fn other_option<'a>(ctx: Context<'a>, name: &str) -> Option<&'a str> {
+    let poise::Context::Application(ctx) = ctx else {
+        return None;
+    };
+    ctx.args
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| match option.value {
+            ResolvedValue::String(value) => Some(value),
+            _ => None,
+        })
+}
+
+async fn suggest(ctx: Context<'_>, kind: ChartKind, typed: &str) -> Vec<AutocompleteChoice> {
+    let library = caller_library(ctx, kind).await;
+    let artist = match kind {
+        ChartKind::Artists => None,
+        _ => other_option(ctx, "artist").filter(|x| !x.trim().is_empty()),
+    };
+    let mut matches: Vec<(usize, &ChartEntry)> = library
+        .iter()
+        .filter(|entry| match (artist, &entry.artist) {
+            (Some(artist), Some(credit)) => match_score(artist, credit).is_some(),
+            _ => true,
+        })
+        .filter_map(|entry| match_score(typed, &entry.name).map(|score| (score, entry)))
+        .collect();
+    // the library is most played first, and sorting is stable, so that breaks ties
+    matches.sort_by_key(|(score, _)| *score);
+
+    let mut seen = vec![];
+    matches
+        .into_iter()
+        .filter(|(_, entry)| {
+            let key = normalize(&entry.name);
+            match seen.contains(&key) {
+                true => false,
+                false => {
+                    seen.push(key);
+                    true
+                }
+            }
+        })
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, entry)| {
+            let label = match (&entry.artist, artist) {
+                (Some(credit), None) => format!("{} by {}", entry.name, credit),
+                _ => entry.name.clone(),
+            };
+            // labels and values are both capped at 100 characters
+            AutocompleteChoice::new(
+                label.chars().take(100).collect::<String>(),
+                entry.name.chars().take(100).collect::<String>(),
+            )
+        })
+        .collect()
+}
+
+pub async fn autocomplete_artist(ctx: Context<'_>, partial: &str) -> Vec<AutocompleteChoice> {
+    suggest(ctx, ChartKind::Artists, partial).await
+}
+
+pub async fn autocomplete_album(ctx: Context<'_>, partial: &str) -> Vec<AutocompleteChoice> {
+    suggest(ctx, ChartKind::Albums, partial).await
+}
+
+pub async fn autocomplete_track(ctx: Context<'_>, partial: &str) -> Vec<AutocompleteChoice> {
+    suggest(ctx, ChartKind::Tracks, partial).await
+}
@@ -1,13 +1,27 @@
-use crate::db::postgres::{get_discord_pairing_code, get_lastfm_username, get_websites};
+use crate::charts::ChartKind;
+use crate::db::postgres::{
+    get_discord_pairing_code, get_lastfm_username, get_listenbrainz_username, get_privacy_setting,
+    get_user_timezone, get_websites, record_guild_member,
+};
 use crate::hos::*;
+use crate::lfm::LastFMApi;
+use crate::listenbrainz::{ListenBrainzApi, ListenBrainzUser};
+use crate::range::{today, StatsRange};
+use chrono::Datelike;
+use chrono_tz::Tz;
 use core::num::NonZeroU16;
 use mljcl::credentials::*;
 use poise::serenity_prelude::*;
 use sqlx::PgPool;
 use std::result::Result;
+use std::sync::Arc;
 use url::{ParseError, Url};
 
-use super::lastfm::LastFMUser;
+use super::autocomplete::{
+    autocomplete_album, autocomplete_artist, autocomplete_track, LibraryCache,
+};
+use super::lastfm::{LastFMService, LastFMUser};
+use super::setups::{MilestoneAnnouncements, Visibility};
 
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
 pub type Context<'a> = poise::Context<'a, BotData, Error>;
@@ -20,13 +34,23 @@ pub struct BotData {
     pub hos_server_passwd: Option<String>,
     pub hos_server_https: bool,
     pub reqwest_client: reqwest::Client,
-    pub lastfm_api: Option<String>,
+    pub lastfm: Option<LastFMApi>,
+    pub listenbrainz: ListenBrainzApi,
+    pub library_cache: Arc<LibraryCache>,
 }
 
 #[derive(Clone, Debug)]
 pub enum MljboardUser {
     MalojaUser(MalojaCredentials),
     LastFMUser(LastFMUser),
+    ListenBrainzUser(ListenBrainzUser),
+}
+
+impl MljboardUser {
+    /// Maloja joins every artist on a scrobble with ", ", other backends keep a credit as one name.
+    pub fn comma_joined_credits(&self) -> bool {
+        matches!(self, MljboardUser::MalojaUser(_))
+    }
 }
 
 fn option_nonzerou16_to_u16(input: Option<NonZeroU16>) -> u16 {
@@ -38,6 +62,13 @@ fn option_nonzerou16_to_u16(input: Option<NonZeroU16>) -> u16 {
     }
 }
 
+/// The name part of a `format_user` string.
+pub fn display_name(formatted_user: &str) -> &str {
+    formatted_user
+        .rsplit_once('#')
+        .map_or(formatted_user, |(name, _)| name)
+}
+
 pub fn format_user(user: User) -> String {
     format!(
         "{}#{}",
@@ -47,13 +78,39 @@ pub fn format_user(user: User) -> String {
 }
 
 impl BotData {
+    /// The Last.FM API pointed at whichever Last.FM-compatible service `lfm` is on.
+    pub fn lastfm_for(&self, lfm: &LastFMUser) -> LastFMApi {
+        self.lastfm
+            .as_ref()
+            .expect("No Last.FM API key passed")
+            .for_service(lfm.service.clone())
+    }
+
+    /// Whether other members may look at `formatted_user`'s stats. Stats are public until the user says otherwise.
+    pub async fn is_public(&self, formatted_user: String) -> bool {
+        let mut public = true;
+        for result in get_privacy_setting(&self.pool, formatted_user).await {
+            public = result.public.unwrap_or(true);
+        }
+        public
+    }
+
+    /// The timezone `formatted_user` set with `/timezone`, if any. Ranges are evaluated in UTC without one.
+    pub async fn timezone(&self, formatted_user: String) -> Option<Tz> {
+        let mut timezone = None;
+        for result in get_user_timezone(&self.pool, formatted_user).await {
+            timezone = result.timezone.and_then(|x| x.parse::<Tz>().ok());
+        }
+        timezone
+    }
+
     pub async fn handle_hos_user(
         &self,
         formatted_user: String,
-        ctx: Context<'_>,
+        ctx: Option<Context<'_>>,
     ) -> Option<MalojaCredentials> {
         let mut assigned_pairing_code: Option<String> = None;
-        for result in get_discord_pairing_code(&self.pool, formatted_user).await {
+        for result in get_discord_pairing_code(&self.pool, formatted_user.clone()).await {
             assigned_pairing_code = result.pairing_code;
         }
         match assigned_pairing_code {
@@ -77,18 +134,39 @@ impl BotData {
                         sessions_with_pairing_code.push(connection.0);
                     }
                 }
+                // the pairing code can be someone else's, when a command is about another member
+                let is_author =
+                    ctx.is_some_and(|ctx| format_user(ctx.author().clone()) == formatted_user);
                 if sessions_with_pairing_code.is_empty() {
-                    ctx.say(
-                        "You have a HOS pairing code, but no client running with it. \
-                    Connect your HOS client.",
-                    )
-                    .await
-                    .unwrap();
+                    if let Some(ctx) = ctx {
+                        let message = match is_author {
+                            true => "You have a HOS pairing code, but no client running with it. \
+                                Connect your HOS client."
+                                .to_string(),
+                            false => format!(
+                                "{} has a HOS pairing code, but no client running with it. \
+                                They need to connect their HOS client.",
+                                display_name(&formatted_user)
+                            ),
+                        };
+                        ctx.say(message).await.unwrap();
+                    }
                     return None;
                 }
                 if sessions_with_pairing_code.len() > 1 {
-                    ctx.say("You have a HOS pairing code, but multiple clients are using it! \
-                            Disconnect every client and reconnect only one, or, alternatively, do `!reset` and try again with one client and a new pairing code.").await.unwrap();
+                    if let Some(ctx) = ctx {
+                        let message = match is_author {
+                            true => "You have a HOS pairing code, but multiple clients are using it! \
+                                Disconnect every client and reconnect only one, or, alternatively, do `!reset` and try again with one client and a new pairing code."
+                                .to_string(),
+                            false => format!(
+                                "{} has a HOS pairing code, but multiple clients are using it! \
+                                They need to disconnect every client and reconnect only one.",
+                                display_name(&formatted_user)
+                            ),
+                        };
+                        ctx.say(message).await.unwrap();
+                    }
                     return None;
                 }
                 let session_id = sessions_with_pairing_code.first().unwrap().to_string();
@@ -108,7 +186,7 @@ impl BotData {
     pub async fn handle_website_user(
         &self,
         formatted_user: String,
-        _ctx: Context<'_>,
+        _ctx: Option<Context<'_>>,
     ) -> Result<MalojaCredentials, Option<ParseError>> {
         let mut assigned_website: Option<String> = None;
 
@@ -148,27 +226,62 @@ impl BotData {
     pub async fn handle_lfm_user(
         &self,
         formatted_user: String,
-        _ctx: Context<'_>,
+        _ctx: Option<Context<'_>>,
     ) -> Option<LastFMUser> {
         let mut assigned_username: Option<String> = None;
+        let mut verified = false;
+        let mut service: Option<String> = None;
 
         for result in get_lastfm_username(&self.pool, formatted_user).await {
             assigned_username = result.lastfm_username;
+            verified = result.session_key.is_some();
+            service = result.service;
         }
 
         if let Some(username) = assigned_username {
-            return Some(LastFMUser { username });
+            return Some(LastFMUser {
+                username,
+                verified,
+                service,
+            });
         }
 
         None
     }
 
+    pub async fn handle_lb_user(
+        &self,
+        formatted_user: String,
+        _ctx: Option<Context<'_>>,
+    ) -> Option<ListenBrainzUser> {
+        let mut assigned_username: Option<String> = None;
+
+        for result in get_listenbrainz_username(&self.pool, formatted_user).await {
+            assigned_username = result.listenbrainz_username;
+        }
+
+        assigned_username.map(|username| ListenBrainzUser { username })
+    }
+
     pub async fn handle_creds(
         &self,
         formatted_user: String,
         ctx: Context<'_>,
     ) -> Option<MljboardUser> {
-        // prioritize website, then HOS, then Last.FM. website probably responds fastest so it comes first
+        self.find_creds(formatted_user, Some(ctx)).await
+    }
+
+    /// Like `handle_creds`, but without telling anyone about a broken HOS setup. For looking up many members at once.
+    pub async fn handle_creds_quietly(&self, formatted_user: String) -> Option<MljboardUser> {
+        self.find_creds(formatted_user, None).await
+    }
+
+    async fn find_creds(
+        &self,
+        formatted_user: String,
+        ctx: Option<Context<'_>>,
+    ) -> Option<MljboardUser> {
+        // prioritize website, then HOS, then Last.FM, then ListenBrainz. website probably responds fastest so it comes first
         let creds = self.handle_website_user(formatted_user.clone(), ctx).await;
         if let Ok(creds) = creds {
             Some(MljboardUser::MalojaUser(creds))
@@ -179,12 +292,64 @@ impl BotData {
                 .map(MljboardUser::MalojaUser)
             {
                 Some(hos_user) => Some(hos_user),
-                None => self
-                    .handle_lfm_user(formatted_user, ctx)
+                None => match self
+                    .handle_lfm_user(formatted_user.clone(), ctx)
+                    .await
+                    .map(MljboardUser::LastFMUser)
+                {
+                    Some(lfm_user) => Some(lfm_user),
+                    None => self
+                        .handle_lb_user(formatted_user, ctx)
+                        .await
+                        .map(MljboardUser::ListenBrainzUser),
+                },
+            }
+        }
+    }
+}
+
+/// Whose stats a command is about: `target` if given, otherwise the author.
+/// Replies and gives `None` if `target` keeps their stats private.
+pub async fn resolve_target(ctx: Context<'_>, target: Option<User>) -> Option<User> {
+    let author = ctx.author().clone();
+    match target {
+        Some(target) if target.id != author.id => {
+            if ctx.data().is_public(format_user(target.clone())).await {
+                Some(target)
+            } else {
+                ctx.say(format!("{} keeps their stats private.", target.name))
                     .await
-                    .map(MljboardUser::LastFMUser),
+                    .unwrap();
+                None
             }
         }
+        _ => Some(author),
+    }
+}
+
+/// Runs before every command, so guild leaderboards know who's in which guild.
+pub async fn track_guild_member(ctx: Context<'_>) {
+    if let Some(guild_id) = ctx.guild_id() {
+        let author = ctx.author().clone();
+        record_guild_member(
+            &ctx.data().pool,
+            guild_id.get() as i64,
+            author.id.get() as i64,
+            format_user(author),
+        )
+        .await;
+    }
+}
+
+/// What to say when `target` hasn't linked anything mljboard can read stats from.
+pub fn no_links_message(ctx: Context<'_>, target: &User) -> String {
+    match target.id == ctx.author().id {
+        true => "You haven't linked a HOS pairing code, a Maloja website, Last.FM or ListenBrainz."
+            .to_string(),
+        false => format!(
+            "{} hasn't linked a HOS pairing code, a Maloja website, Last.FM or ListenBrainz.",
+            target.name
+        ),
     }
 }
 
@@ -218,13 +383,46 @@ pub async fn website_setup(
 pub async fn lfm_setup(
     ctx: poise::Context<'_, BotData, Error>,
     #[description = "Last.FM username"] username: String,
+    #[description = "Service the account is on, Last.FM if left out"] service: Option<
+        LastFMService,
+    >,
+    #[description = "API URL of your GNU FM server, i.e. https://example.com/2.0/"] api_url: Option<
+        String,
+    >,
 ) -> Result<(), Error> {
     let formatted_user = format_user(ctx.author().clone());
-    super::setups::lfm_setup(ctx, &ctx.data().pool, formatted_user, username).await;
+    super::setups::lfm_setup(
+        ctx,
+        &ctx.data().pool,
+        formatted_user,
+        username,
+        service.unwrap_or(LastFMService::LastFM),
+        api_url,
+    )
+    .await;
     Ok(())
 }
 
-/// Unlink HOS, websites, and Last.FM accounts.
+/// Add a ListenBrainz account.
+#[poise::command(slash_command)]
+pub async fn lb_setup(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "ListenBrainz username"] username: String,
+) -> Result<(), Error> {
+    let formatted_user = format_user(ctx.author().clone());
+    super::setups::lb_setup(ctx, &ctx.data().pool, formatted_user, username).await;
+    Ok(())
+}
+
+/// Prove you own your linked Last.FM account by logging in on Last.FM.
+#[poise::command(slash_command)]
+pub async fn lfm_verify(ctx: poise::Context<'_, BotData, Error>) -> Result<(), Error> {
+    let formatted_user = format_user(ctx.author().clone());
+    super::setups::lfm_verify(ctx, &ctx.data().pool, formatted_user).await;
+    Ok(())
+}
+
+/// Unlink HOS, websites, Last.FM and ListenBrainz accounts.
 #[poise::command(slash_command)]
 pub async fn reset(ctx: poise::Context<'_, BotData, Error>) -> Result<(), Error> {
     let formatted_user = format_user(ctx.author().clone());
@@ -232,57 +430,178 @@ pub async fn reset(ctx: poise::Context<'_, BotData, Error>) -> Result<(), Error>
     Ok(())
 }
 
-/// Get your scrobbles alltime and within a year.
+/// Get your or another member's scrobbles alltime and within a range, this year by default.
 #[poise::command(slash_command)]
-pub async fn scrobbles(ctx: poise::Context<'_, BotData, Error>) -> Result<(), Error> {
-    let formatted_user = format_user(ctx.author().clone());
+pub async fn scrobbles(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "today, week, month, year, 2024, March 2024 or 2024-01-01..2024-02-15"]
+    range: Option<StatsRange>,
+    #[description = "Member"] user: Option<User>,
+) -> Result<(), Error> {
+    let Some(target) = resolve_target(ctx, user).await else {
+        return Ok(());
+    };
+    let formatted_user = format_user(target.clone());
+    let tz = ctx.data().timezone(formatted_user.clone()).await;
     let user = ctx.data().handle_creds(formatted_user, ctx).await;
 
     if user.is_none() {
-        ctx.say("You don't have a HOS pairing code or a website set up.")
-            .await
-            .unwrap();
+        ctx.say(no_links_message(ctx, &target)).await.unwrap();
         return Ok(());
     }
 
-    super::ops::scrobbles_cmd(ctx.data().reqwest_client.clone(), user, None, ctx).await;
+    super::ops::scrobbles_cmd(
+        ctx.data().reqwest_client.clone(),
+        user,
+        None,
+        ctx,
+        range.unwrap_or(StatsRange::Year),
+        tz,
+        target,
+    )
+    .await;
     Ok(())
 }
 
-/// Get the amount of scrobbles you have for a singular artist.
+/// Get the amount of scrobbles you or another member have for a singular artist.
 #[poise::command(slash_command)]
 pub async fn artistscrobbles(
     ctx: poise::Context<'_, BotData, Error>,
-    #[description = "Artist"] artist: String,
+    #[description = "Artist"]
+    #[autocomplete = "autocomplete_artist"]
+    artist: String,
+    #[description = "today, week, month, year, 2024, March 2024 or 2024-01-01..2024-02-15"]
+    range: Option<StatsRange>,
+    #[description = "Member"] user: Option<User>,
 ) -> Result<(), Error> {
-    let formatted_user = format_user(ctx.author().clone());
+    let Some(target) = resolve_target(ctx, user).await else {
+        return Ok(());
+    };
+    let formatted_user = format_user(target.clone());
+    let tz = ctx.data().timezone(formatted_user.clone()).await;
     let user = ctx.data().handle_creds(formatted_user, ctx).await;
 
     if user.is_none() {
-        ctx.say("You don't have a HOS pairing code or a website set up.")
-            .await
-            .unwrap();
+        ctx.say(no_links_message(ctx, &target)).await.unwrap();
         return Ok(());
     }
 
-    super::ops::artistscrobbles_cmd(ctx.data().reqwest_client.clone(), user, None, ctx, artist)
-        .await;
+    super::ops::artistscrobbles_cmd(
+        ctx.data().reqwest_client.clone(),
+        user,
+        None,
+        ctx,
+        artist,
+        range,
+        tz,
+        target,
+    )
+    .await;
+    Ok(())
+}
+
+/// Get the amount of scrobbles you or another member have for a track.
+#[poise::command(slash_command)]
+pub async fn trackscrobbles(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "Artist"]
+    #[autocomplete = "autocomplete_artist"]
+    artist: String,
+    #[description = "Title"]
+    #[autocomplete = "autocomplete_track"]
+    title: String,
+    #[description = "today, week, month, year, 2024, March 2024 or 2024-01-01..2024-02-15"]
+    range: Option<StatsRange>,
+    #[description = "Member"] user: Option<User>,
+) -> Result<(), Error> {
+    let Some(target) = resolve_target(ctx, user).await else {
+        return Ok(());
+    };
+    let formatted_user = format_user(target.clone());
+    let tz = ctx.data().timezone(formatted_user.clone()).await;
+    let user = ctx.data().handle_creds(formatted_user, ctx).await;
+
+    if user.is_none() {
+        ctx.say(no_links_message(ctx, &target)).await.unwrap();
+        return Ok(());
+    }
+
+    super::ops::itemscrobbles_cmd(
+        ctx.data().reqwest_client.clone(),
+        user,
+        None,
+        ctx,
+        ChartKind::Tracks,
+        artist,
+        title,
+        range,
+        tz,
+        target,
+    )
+    .await;
+    Ok(())
+}
+
+/// Get the amount of scrobbles you or another member have for an album.
+#[poise::command(slash_command)]
+pub async fn albumscrobbles(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "Artist"]
+    #[autocomplete = "autocomplete_artist"]
+    artist: String,
+    #[description = "Album"]
+    #[autocomplete = "autocomplete_album"]
+    album: String,
+    #[description = "today, week, month, year, 2024, March 2024 or 2024-01-01..2024-02-15"]
+    range: Option<StatsRange>,
+    #[description = "Member"] user: Option<User>,
+) -> Result<(), Error> {
+    let Some(target) = resolve_target(ctx, user).await else {
+        return Ok(());
+    };
+    let formatted_user = format_user(target.clone());
+    let tz = ctx.data().timezone(formatted_user.clone()).await;
+    let user = ctx.data().handle_creds(formatted_user, ctx).await;
+
+    if user.is_none() {
+        ctx.say(no_links_message(ctx, &target)).await.unwrap();
+        return Ok(());
+    }
+
+    super::ops::itemscrobbles_cmd(
+        ctx.data().reqwest_client.clone(),
+        user,
+        None,
+        ctx,
+        ChartKind::Albums,
+        artist,
+        album,
+        range,
+        tz,
+        target,
+    )
+    .await;
     Ok(())
 }
 
-/// A grid of your top listened albums of all time.
+/// A grid of your or another member's top listened albums, of all time by default.
 #[poise::command(slash_command, prefix_command)]
 pub async fn grid(
     ctx: poise::Context<'_, BotData, Error>,
     #[description = "Square size"] square_size: usize,
+    #[description = "today, week, month, year, 2024, March 2024 or 2024-01-01..2024-02-15"]
+    range: Option<StatsRange>,
+    #[description = "Member"] user: Option<User>,
 ) -> Result<(), Error> {
-    let formatted_user = format_user(ctx.author().clone());
+    let Some(target) = resolve_target(ctx, user).await else {
+        return Ok(());
+    };
+    let formatted_user = format_user(target.clone());
+    let tz = ctx.data().timezone(formatted_user.clone()).await;
     let user = ctx.data().handle_creds(formatted_user, ctx).await;
 
     if user.is_none() {
-        ctx.say("You don't have a HOS pairing code or a website set up.")
-            .await
-            .unwrap();
+        ctx.say(no_links_message(ctx, &target)).await.unwrap();
         return Ok(());
     }
 
@@ -292,18 +611,384 @@ pub async fn grid(
         None,
         ctx,
         square_size,
-        mljcl::range::Range::AllTime,
+        range.unwrap_or(StatsRange::AllTime),
+        tz,
+        target,
     )
     .await;
     Ok(())
 }
 
+/// Your or another member's most played artists, albums or tracks.
+#[poise::command(slash_command)]
+pub async fn top(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "What to rank"] kind: ChartKind,
+    #[description = "today, week, month, year, 2024, March 2024 or 2024-01-01..2024-02-15"]
+    range: Option<StatsRange>,
+    #[description = "Member"] user: Option<User>,
+) -> Result<(), Error> {
+    let Some(target) = resolve_target(ctx, user).await else {
+        return Ok(());
+    };
+    let formatted_user = format_user(target.clone());
+    let tz = ctx.data().timezone(formatted_user.clone()).await;
+    let user = ctx.data().handle_creds(formatted_user, ctx).await;
+
+    if user.is_none() {
+        ctx.say(no_links_message(ctx, &target)).await.unwrap();
+        return Ok(());
+    }
+
+    super::ops::top_cmd(
+        ctx.data().reqwest_client.clone(),
+        user,
+        None,
+        ctx,
+        kind,
+        range.unwrap_or(StatsRange::AllTime),
+        tz,
+        target,
+    )
+    .await;
+    Ok(())
+}
+
+/// See how much your taste overlaps with another member's.
+#[poise::command(slash_command)]
+pub async fn compare(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "Member"] user: User,
+    #[description = "today, week, month, year, 2024, March 2024 or 2024-01-01..2024-02-15"]
+    range: Option<StatsRange>,
+) -> Result<(), Error> {
+    if user.id == ctx.author().id {
+        ctx.say("Pick someone other than yourself to compare with.")
+            .await
+            .unwrap();
+        return Ok(());
+    }
+    let Some(target) = resolve_target(ctx, Some(user)).await else {
+        return Ok(());
+    };
+
+    let formatted_user = format_user(ctx.author().clone());
+    let tz = ctx.data().timezone(formatted_user.clone()).await;
+    let user = ctx.data().handle_creds(formatted_user, ctx).await;
+    if user.is_none() {
+        ctx.say(no_links_message(ctx, ctx.author())).await.unwrap();
+        return Ok(());
+    }
+    let Some(other_user) = ctx
+        .data()
+        .handle_creds(format_user(target.clone()), ctx)
+        .await
+    else {
+        ctx.say(no_links_message(ctx, &target)).await.unwrap();
+        return Ok(());
+    };
+
+    super::ops::compare_cmd(
+        ctx.data().reqwest_client.clone(),
+        user,
+        None,
+        ctx,
+        other_user,
+        range.unwrap_or(StatsRange::AllTime),
+        tz,
+        target,
+    )
+    .await;
+    Ok(())
+}
+
+/// See who in this server has listened to an artist the most.
+#[poise::command(slash_command, guild_only)]
+pub async fn whoknows(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "Artist"]
+    #[autocomplete = "autocomplete_artist"]
+    artist: String,
+) -> Result<(), Error> {
+    super::guild::whoknows_cmd(ctx.data().reqwest_client.clone(), ctx, artist).await;
+    Ok(())
+}
+
+/// List the artist crowns you or another member hold in this server.
+#[poise::command(slash_command, guild_only)]
+pub async fn crowns(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "Member"] user: Option<User>,
+) -> Result<(), Error> {
+    let Some(target) = resolve_target(ctx, user).await else {
+        return Ok(());
+    };
+    super::guild::crowns_cmd(ctx, target).await;
+    Ok(())
+}
+
+/// How many days in a row you or another member have been listening, optionally to one artist.
+#[poise::command(slash_command)]
+pub async fn streak(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "Only count this artist"]
+    #[autocomplete = "autocomplete_artist"]
+    artist: Option<String>,
+    #[description = "Member"] user: Option<User>,
+) -> Result<(), Error> {
+    let Some(target) = resolve_target(ctx, user).await else {
+        return Ok(());
+    };
+    let formatted_user = format_user(target.clone());
+    let tz = ctx.data().timezone(formatted_user.clone()).await;
+    let user = ctx.data().handle_creds(formatted_user, ctx).await;
+
+    if user.is_none() {
+        ctx.say(no_links_message(ctx, &target)).await.unwrap();
+        return Ok(());
+    }
+
+    super::ops::streak_cmd(
+        ctx.data().reqwest_client.clone(),
+        user,
+        None,
+        ctx,
+        artist,
+        tz,
+        target,
+    )
+    .await;
+    Ok(())
+}
+
+/// Scrobbles per day over a month, this month by default.
+#[poise::command(slash_command)]
+pub async fn calendar(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "month, March 2024 or 2024-03"] month: Option<StatsRange>,
+    #[description = "Member"] user: Option<User>,
+) -> Result<(), Error> {
+    let Some(target) = resolve_target(ctx, user).await else {
+        return Ok(());
+    };
+    let formatted_user = format_user(target.clone());
+    let tz = ctx.data().timezone(formatted_user.clone()).await;
+    let user = ctx.data().handle_creds(formatted_user, ctx).await;
+
+    if user.is_none() {
+        ctx.say(no_links_message(ctx, &target)).await.unwrap();
+        return Ok(());
+    }
+
+    super::ops::calendar_cmd(
+        ctx.data().reqwest_client.clone(),
+        user,
+        None,
+        ctx,
+        month.unwrap_or(StatsRange::Month),
+        tz,
+        target,
+    )
+    .await;
+    Ok(())
+}
+
+/// When during the week you or another member listen, all time by default.
+#[poise::command(slash_command)]
+pub async fn heatmap(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "today, week, month, year, 2024, March 2024 or 2024-01-01..2024-02-15"]
+    range: Option<StatsRange>,
+    #[description = "Member"] user: Option<User>,
+) -> Result<(), Error> {
+    let Some(target) = resolve_target(ctx, user).await else {
+        return Ok(());
+    };
+    let formatted_user = format_user(target.clone());
+    let tz = ctx.data().timezone(formatted_user.clone()).await;
+    let user = ctx.data().handle_creds(formatted_user, ctx).await;
+
+    if user.is_none() {
+        ctx.say(no_links_message(ctx, &target)).await.unwrap();
+        return Ok(());
+    }
+
+    super::ops::heatmap_cmd(
+        ctx.data().reqwest_client.clone(),
+        user,
+        None,
+        ctx,
+        range.unwrap_or(StatsRange::AllTime),
+        tz,
+        target,
+    )
+    .await;
+    Ok(())
+}
+
+/// Find your or another member's Nth scrobble.
+#[poise::command(slash_command)]
+pub async fn milestone(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "Which scrobble, 1 being the first"] n: u64,
+    #[description = "Member"] user: Option<User>,
+) -> Result<(), Error> {
+    let Some(target) = resolve_target(ctx, user).await else {
+        return Ok(());
+    };
+    let formatted_user = format_user(target.clone());
+    let tz = ctx.data().timezone(formatted_user.clone()).await;
+    let user = ctx.data().handle_creds(formatted_user, ctx).await;
+
+    if user.is_none() {
+        ctx.say(no_links_message(ctx, &target)).await.unwrap();
+        return Ok(());
+    }
+
+    super::milestones::milestone_cmd(
+        ctx.data().reqwest_client.clone(),
+        user,
+        None,
+        ctx,
+        n,
+        tz,
+        target,
+    )
+    .await;
+    Ok(())
+}
+
+/// A look back at a year of your or another member's listening, this year by default.
+#[poise::command(slash_command)]
+pub async fn wrapped(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "Year"] year: Option<i32>,
+    #[description = "Member"] user: Option<User>,
+) -> Result<(), Error> {
+    let Some(target) = resolve_target(ctx, user).await else {
+        return Ok(());
+    };
+    let formatted_user = format_user(target.clone());
+    let tz = ctx.data().timezone(formatted_user.clone()).await;
+    let user = ctx.data().handle_creds(formatted_user, ctx).await;
+
+    if user.is_none() {
+        ctx.say(no_links_message(ctx, &target)).await.unwrap();
+        return Ok(());
+    }
+
+    super::ops::wrapped_cmd(
+        ctx.data().reqwest_client.clone(),
+        user,
+        None,
+        ctx,
+        year.unwrap_or(today(tz).year()),
+        tz,
+        target,
+    )
+    .await;
+    Ok(())
+}
+
+/// Choose whether other members can look up your stats.
+#[poise::command(slash_command)]
+pub async fn privacy(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "Who can see your stats"] visibility: Visibility,
+) -> Result<(), Error> {
+    let formatted_user = format_user(ctx.author().clone());
+    super::setups::privacy(ctx, &ctx.data().pool, formatted_user, visibility).await;
+    Ok(())
+}
+
+/// Choose where to hear about passing round numbers of scrobbles.
+#[poise::command(slash_command)]
+pub async fn milestones(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "Where to announce them"] announcements: MilestoneAnnouncements,
+) -> Result<(), Error> {
+    let formatted_user = format_user(ctx.author().clone());
+    super::setups::milestones(ctx, &ctx.data().pool, formatted_user, announcements).await;
+    Ok(())
+}
+
+/// Set the timezone your days, months and years are counted in.
+#[poise::command(slash_command)]
+pub async fn timezone(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "A name like America/Los_Angeles or Europe/Berlin, leave out to see yours"]
+    timezone: Option<String>,
+) -> Result<(), Error> {
+    let formatted_user = format_user(ctx.author().clone());
+    super::setups::timezone(ctx, &ctx.data().pool, formatted_user, timezone).await;
+    Ok(())
+}
+
+/// Show your or another member's latest scrobbles.
+#[poise::command(slash_command)]
+pub async fn recent(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "Member"] user: Option<User>,
+) -> Result<(), Error> {
+    let Some(target) = resolve_target(ctx, user).await else {
+        return Ok(());
+    };
+    let formatted_user = format_user(target.clone());
+    let tz = ctx.data().timezone(formatted_user.clone()).await;
+    let user = ctx.data().handle_creds(formatted_user, ctx).await;
+
+    if user.is_none() {
+        ctx.say(no_links_message(ctx, &target)).await.unwrap();
+        return Ok(());
+    }
+
+    super::ops::recent_cmd(
+        ctx.data().reqwest_client.clone(),
+        user,
+        None,
+        ctx,
+        tz,
+        target,
+    )
+    .await;
+    Ok(())
+}
+
+/// Show what you or another member is listening to right now.
+#[poise::command(slash_command, rename = "np")]
+pub async fn nowplaying(
+    ctx: poise::Context<'_, BotData, Error>,
+    #[description = "Member"] user: Option<User>,
+) -> Result<(), Error> {
+    let Some(target) = resolve_target(ctx, user).await else {
+        return Ok(());
+    };
+    let formatted_user = format_user(target.clone());
+    let user = ctx.data().handle_creds(formatted_user, ctx).await;
+
+    if user.is_none() {
+        ctx.say(no_links_message(ctx, &target)).await.unwrap();
+        return Ok(());
+    }
+
+    super::ops::np_cmd(ctx.data().reqwest_client.clone(), user, None, ctx, target).await;
+    Ok(())
+}
+
+/// Check how up to date mljboard's copy of your Last.FM scrobbles is.
+#[poise::command(slash_command, rename = "sync")]
+pub async fn sync_status(ctx: poise::Context<'_, BotData, Error>) -> Result<(), Error> {
+    let formatted_user = format_user(ctx.author().clone());
+    super::lastfm::sync_cmd(ctx, &ctx.data().pool, formatted_user).await;
+    Ok(())
+}
+
 /// Gets the info about a Last.FM user.
 #[poise::command(slash_command)]
 pub async fn lfmuser(
     ctx: poise::Context<'_, BotData, Error>,
     #[description = "User"] user: String,
 ) -> Result<(), Error> {
-    super::lastfm::lfmuser_cmd(ctx, ctx.data().lastfm_api.clone(), user).await;
+    super::lastfm::lfmuser_cmd(ctx, ctx.data().lastfm.clone(), user).await;
     Ok(())
 }
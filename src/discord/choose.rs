@@ -0,0 +1,84 @@
+use super::bot::Context;
+use poise::{serenity_prelude::*, CreateReply};
+use std::time::Duration;
+
+/// How long the caller has to pick before the menu stops working.
+const CHOICE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Discord won't show more options than this in one menu.
+pub const MAX_CHOICES: usize = 25;
+
+/// Select menu labels are capped at 100 characters.
+fn label(option: &str) -> String {
+    match option.chars().count() > 100 {
+        true => format!("{}…", option.chars().take(99).collect::<String>()),
+        false => option.to_string(),
+    }
+}
+
+/// Asks the caller to pick one of `options` from a menu, returning the index they picked,
+/// or `None` if they didn't pick anything in time.
+pub async fn choose(ctx: Context<'_>, prompt: String, options: &[String]) -> Option<usize> {
+    let menu = CreateSelectMenu::new(
+        "choice",
+        CreateSelectMenuKind::String {
+            options: options
+                .iter()
+                .take(MAX_CHOICES)
+                .enumerate()
+                .map(|(i, option)| CreateSelectMenuOption::new(label(option), i.to_string()))
+                .collect(),
+        },
+    )
+    .placeholder("Pick one");
+    let message = ctx
+        .send(
+            CreateReply::default()
+                .content(prompt.clone())
+                .components(vec![CreateActionRow::SelectMenu(menu)]),
+        )
+        .await
+        .unwrap();
+
+    let pick = message
+        .message()
+        .await
+        .unwrap()
+        .await_component_interaction(ctx)
+        .author_id(ctx.author().id)
+        .timeout(CHOICE_TIMEOUT)
+        .await;
+
+    let Some(pick) = pick else {
+        let _ = message
+            .edit(
+                ctx,
+                CreateReply::default()
+                    .content(format!("{}\nNothing was picked.", prompt))
+                    .components(vec![]),
+            )
+            .await;
+        return None;
+    };
+    let index = match &pick.data.kind {
+        ComponentInteractionDataKind::StringSelect { values } => values
+            .first()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|index| *index < options.len()),
+        _ => None,
+    };
+    pick.create_response(
+        ctx,
+        CreateInteractionResponse::UpdateMessage(
+            CreateInteractionResponseMessage::new()
+                .content(match index {
+                    Some(index) => format!("{}\nPicked **{}**.", prompt, options[index]),
+                    None => format!("{}\nNothing was picked.", prompt),
+                })
+                .components(vec![]),
+        ),
+    )
+    .await
+    .unwrap();
+    index
+}
@@ -0,0 +1,292 @@
+use super::bot::{format_user, Context};
+use super::ops::member_artist_count;
+use super::paginate::paginate;
+use crate::db::postgres::{
+    get_crown, get_guild_members, get_user_crowns, set_crown, update_crown_playcount, ArtistCrown,
+};
+use crate::lfm::sync::unix_now;
+use crate::normalize::artist_key;
+use futures::stream::{self, StreamExt};
+use poise::CreateReply;
+use reqwest::Client;
+use serenity::all::{CreateEmbed, CreateEmbedFooter, User, UserId};
+
+/// How many members' backends are asked at once. Maloja servers are run by the members themselves, so keep it gentle.
+pub const LEADERBOARD_CONCURRENCY: usize = 8;
+pub const LEADERBOARD_SHOWN: usize = 15;
+/// The fewest plays that earn a crown, so nobody gets one for an artist they heard once.
+pub const CROWN_THRESHOLD: u64 = 30;
+pub const CROWNS_PAGE_SIZE: usize = 20;
+
+#[derive(Clone, Debug)]
+pub struct LeaderboardEntry {
+    pub user_id: UserId,
+    pub formatted_user: String,
+    pub count: u64,
+    /// Whether others can see this member's stats. Only public members can hold crowns.
+    pub public: bool,
+}
+
+/// All-time scrobbles of `artist` for every linked member of `guild_id` who lets others see their stats, most first.
+/// The caller is always counted, private or not, since it's their own number, but only public members are marked `public`.
+pub async fn artist_leaderboard(
+    client: Client,
+    ctx: Context<'_>,
+    guild_id: i64,
+    artist: String,
+) -> Vec<LeaderboardEntry> {
+    let members = get_guild_members(&ctx.data().pool, guild_id).await;
+    let author_id = ctx.author().id;
+
+    let mut entries: Vec<LeaderboardEntry> = stream::iter(members)
+        .filter_map(|member| async move {
+            let user_id = UserId::new(member.user_id? as u64);
+            let formatted_user = member.discord_username?;
+            Some((user_id, formatted_user))
+        })
+        .map(|(user_id, formatted_user)| {
+            let client = client.clone();
+            let artist = artist.clone();
+            async move {
+                let public = ctx.data().is_public(formatted_user.clone()).await;
+                if user_id != author_id && !public {
+                    return None;
+                }
+                let user = ctx
+                    .data()
+                    .handle_creds_quietly(formatted_user.clone())
+                    .await?;
+                let count = member_artist_count(client, ctx, user, artist).await.ok()?;
+                Some(LeaderboardEntry {
+                    user_id,
+                    formatted_user,
+                    count,
+                    public,
+                })
+            }
+        })
+        .buffer_unordered(LEADERBOARD_CONCURRENCY)
+        .filter_map(|entry| async move { entry.filter(|x| x.count > 0) })
+        .collect()
+        .await;
+
+    entries.sort_by(|a, b| b.count.cmp(&a.count));
+    entries
+}
+
+pub enum CrownChange {
+    Claimed,
+    Stolen { from: UserId },
+}
+
+/// Hands the crown for `artist` in `guild_id` to `user_id` if they've earned it.
+/// `known_counts` are counts fetched alongside this one, the holder's is used over the stored one when it's in there.
+/// Otherwise the holder's count is fetched again before the crown changes hands.
+pub async fn update_crown(
+    ctx: Context<'_>,
+    guild_id: i64,
+    artist: &str,
+    user_id: UserId,
+    formatted_user: String,
+    count: u64,
+    known_counts: &[LeaderboardEntry],
+) -> Option<CrownChange> {
+    let pool = &ctx.data().pool;
+    let artist_key = artist_key(artist);
+    let crown = get_crown(pool, guild_id, artist_key.clone())
+        .await
+        .into_iter()
+        .next();
+    let new_crown = ArtistCrown {
+        guild_id: Some(guild_id),
+        artist_key: Some(artist_key.clone()),
+        artist: Some(artist.to_string()),
+        user_id: Some(user_id.get() as i64),
+        discord_username: Some(formatted_user),
+        playcount: Some(count as i64),
+        claimed_playcount: Some(count as i64),
+        claimed_at: Some(unix_now()),
+    };
+
+    let holder = crown
+        .as_ref()
+        .and_then(|x| x.user_id)
+        .map(|x| UserId::new(x as u64));
+    let Some(holder) = holder else {
+        if count < CROWN_THRESHOLD {
+            return None;
+        }
+        set_crown(pool, new_crown).await;
+        return Some(CrownChange::Claimed);
+    };
+    if holder == user_id {
+        update_crown_playcount(pool, guild_id, artist_key, count as i64).await;
+        return None;
+    }
+
+    let stored_count = crown.as_ref().and_then(|x| x.playcount).unwrap_or(0) as u64;
+    let holder_count = match known_counts.iter().find(|x| x.user_id == holder) {
+        Some(entry) => Some(entry.count),
+        // the stored count can be long out of date, check it before taking the crown away
+        None if count >= CROWN_THRESHOLD && count > stored_count => {
+            let holder_user = crown.and_then(|x| x.discord_username);
+            match holder_artist_count(ctx, holder_user, artist).await {
+                Some(holder_count) => Some(holder_count),
+                // couldn't check, leave the crown where it is
+                None => return None,
+            }
+        }
+        None => None,
+    };
+    if count >= CROWN_THRESHOLD && count > holder_count.unwrap_or(stored_count) {
+        set_crown(pool, new_crown).await;
+        return Some(CrownChange::Stolen { from: holder });
+    }
+    if let Some(holder_count) = holder_count.filter(|x| *x != stored_count) {
+        update_crown_playcount(pool, guild_id, artist_key, holder_count as i64).await;
+    }
+    None
+}
+
+/// The crown holder's all-time scrobbles of `artist` right now. 0 if they've unlinked everything,
+/// `None` if their backend couldn't be reached.
+async fn holder_artist_count(
+    ctx: Context<'_>,
+    formatted_user: Option<String>,
+    artist: &str,
+) -> Option<u64> {
+    let Some(user) = ctx.data().handle_creds_quietly(formatted_user?).await else {
+        return Some(0);
+    };
+    member_artist_count(
+        ctx.data().reqwest_client.clone(),
+        ctx,
+        user,
+        artist.to_string(),
+    )
+    .await
+    .ok()
+}
+
+pub fn crown_change_message(change: &CrownChange, user_id: UserId, artist: &str) -> String {
+    match change {
+        CrownChange::Claimed => format!("👑 <@{}> claimed the crown for {}!", user_id, artist),
+        CrownChange::Stolen { from } => format!(
+            "👑 <@{}> took the crown for {} from <@{}>!",
+            user_id, artist, from
+        ),
+    }
+}
+
+pub async fn whoknows_cmd(client: Client, ctx: Context<'_>, artist: String) {
+    let Some(guild_id) = ctx.guild_id() else {
+        return;
+    };
+    ctx.defer().await.unwrap();
+
+    let leaderboard = artist_leaderboard(client, ctx, guild_id.get() as i64, artist.clone()).await;
+    if leaderboard.is_empty() {
+        ctx.reply(format!("Nobody here has listened to {} yet.", artist))
+            .await
+            .unwrap();
+        return;
+    }
+
+    let author = format_user(ctx.author().clone());
+    let lines = leaderboard
+        .iter()
+        .take(LEADERBOARD_SHOWN)
+        .enumerate()
+        .map(|(i, entry)| {
+            let line = format!("{}. <@{}> - {} plays", i + 1, entry.user_id, entry.count);
+            match entry.formatted_user == author {
+                true => format!("**{}**", line),
+                false => line,
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    let total: u64 = leaderboard.iter().map(|x| x.count).sum();
+
+    // a private caller sees their own row, but crowns are shown in /crowns, so they can't win one
+    let mut crown_message = None;
+    if let Some(top) = leaderboard.iter().find(|x| x.public) {
+        if let Some(change) = update_crown(
+            ctx,
+            guild_id.get() as i64,
+            &artist,
+            top.user_id,
+            top.formatted_user.clone(),
+            top.count,
+            &leaderboard,
+        )
+        .await
+        {
+            crown_message = Some(crown_change_message(&change, top.user_id, &artist));
+        }
+    }
+    let description = match crown_message {
+        Some(message) => format!("{}\n\n{}", lines, message),
+        None => lines,
+    };
+
+    let embed = CreateEmbed::new()
+        .title(format!("Who knows {}?", artist))
+        .description(description)
+        .footer(CreateEmbedFooter::new(format!(
+            "{} listeners, {} plays",
+            leaderboard.len(),
+            total
+        )));
+    ctx.send(CreateReply::default().embed(embed)).await.unwrap();
+}
+
+pub async fn crowns_cmd(ctx: Context<'_>, target: User) {
+    let Some(guild_id) = ctx.guild_id() else {
+        return;
+    };
+    let crowns = get_user_crowns(
+        &ctx.data().pool,
+        guild_id.get() as i64,
+        target.id.get() as i64,
+    )
+    .await;
+    if crowns.is_empty() {
+        ctx.reply(format!("{} doesn't hold any crowns here.", target.name))
+            .await
+            .unwrap();
+        return;
+    }
+
+    let page_count = crowns.len().div_ceil(CROWNS_PAGE_SIZE);
+    let pages = crowns
+        .chunks(CROWNS_PAGE_SIZE)
+        .enumerate()
+        .map(|(page, chunk)| {
+            let lines = chunk
+                .iter()
+                .enumerate()
+                .map(|(i, crown)| {
+                    format!(
+                        "{}. **{}** - {} plays, claimed <t:{}:R>",
+                        page * CROWNS_PAGE_SIZE + i + 1,
+                        crown.artist.clone().unwrap_or_default(),
+                        crown.playcount.unwrap_or(0),
+                        crown.claimed_at.unwrap_or(0)
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join("\n");
+            CreateEmbed::new()
+                .title(format!("👑 {}'s crowns ({})", target.name, crowns.len()))
+                .description(lines)
+                .footer(CreateEmbedFooter::new(format!(
+                    "Page {} of {}",
+                    page + 1,
+                    page_count
+                )))
+        })
+        .collect();
+
+    paginate(ctx, pages).await;
+}
@@ -1,23 +1,40 @@
-use super::bot::Context;
-use crate::lfm::*;
-use futures_util::pin_mut;
-use futures_util::stream::StreamExt;
-use lastfm::{client::RecentTracksFetcher, track::RecordedTrack};
+use super::bot::{format_user, Context};
+use crate::db::postgres::{
+    count_lastfm_scrobbles, get_lastfm_scrobbles, get_lastfm_sync_status, get_lastfm_username,
+    get_latest_lastfm_scrobble_time, record_lastfm_sync_success, LastFMScrobble,
+};
+use crate::lfm::sync::{next_attempt, store_lastfm_scrobbles, unix_now};
+use crate::lfm::LastFMApi;
+use crate::range::StatsRange;
 use poise::{serenity_prelude::*, CreateReply, ReplyHandle};
 use serenity::all::CreateEmbed;
-use std::{future::IntoFuture, time::SystemTime};
+use sqlx::PgPool;
+use std::future::IntoFuture;
 
 const LOADING_GIF: &str = "https://media1.tenor.com/m/mRbYKHgYCOIAAAAC/loading-gif-loading.gif";
 
 #[derive(Clone, Debug)]
 pub struct LastFMUser {
     pub username: String,
+    pub verified: bool,
+    /// API base URL of a Last.FM-compatible service like Libre.fm, `None` for Last.FM itself.
+    pub service: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, poise::ChoiceParameter)]
+pub enum LastFMService {
+    #[name = "Last.FM"]
+    LastFM,
+    #[name = "Libre.fm"]
+    LibreFM,
+    #[name = "Another GNU FM server"]
+    GnuFM,
 }
 
 #[derive(Clone, Debug)]
 pub struct LfmRange {
-    start: Option<i64>,
-    end: Option<i64>,
+    pub start: Option<i64>,
+    pub end: Option<i64>,
 }
 
 impl LfmRange {
@@ -44,58 +61,61 @@ pub fn get_loading_message(username: String, loaded: Option<usize>) -> CreateRep
 }
 
 pub async fn get_streams(
-    recent_tracks: Result<RecentTracksFetcher, lastfm::errors::Error>,
+    lastfm: &LastFMApi,
+    from: Option<i64>,
     message: ReplyHandle<'_>,
     ctx: Context<'_>,
     username: String,
-) -> Option<Vec<RecordedTrack>> {
-    let recent_stream = recent_tracks.map(|x| x.into_stream());
-    if let Ok(recent_stream) = recent_stream {
-        pin_mut!(recent_stream);
-        let mut tracks = vec![];
-        while let Some(track) = recent_stream.next().await {
-            tracks.push(track.unwrap());
-            if tracks.len() % 1000 == 0 {
-                message
-                    .edit(
-                        ctx,
-                        get_loading_message(username.clone(), Some(tracks.len())),
-                    )
+) -> Option<usize> {
+    // pages don't come in round numbers, so update whenever another thousand have been passed
+    let mut shown = 0;
+    store_lastfm_scrobbles(&ctx.data().pool, lastfm, username.clone(), from, |loaded| {
+        let update = loaded / 1000 > shown / 1000;
+        if update {
+            shown = loaded;
+        }
+        let message = message.clone();
+        let username = username.clone();
+        async move {
+            if update {
+                if let Err(err) = message
+                    .edit(ctx, get_loading_message(username, Some(loaded)))
                     .await
-                    .expect("Error editing message");
+                {
+                    log::warn!("Error editing loading message: {}", err);
+                }
             }
         }
-
-        return Some(tracks);
-    }
-    None
+    })
+    .await
+    .ok()
 }
 
-pub async fn get_lastfm_user(
-    ctx: Context<'_>,
-    api: String,
-    username: String,
-    lfm_range: LfmRange,
-) -> Option<Vec<RecordedTrack>> {
-    // TODO: caching will skip this step or parts of it *if available*
+/// Brings the scrobble cache for `username` up to date behind a loading message the caller can cancel.
+/// Only scrobbles newer than the latest cached one are fetched from Last.FM. `false` when there's nothing
+/// to read from the cache: the user wasn't found, the request failed or the sync was cancelled before it had anything.
+pub async fn sync_lastfm_cache(ctx: Context<'_>, lastfm: &LastFMApi, username: String) -> bool {
+    // Last.FM usernames are case-insensitive, don't cache the same user twice
+    let cache_key = username.to_lowercase();
+    let pool = &ctx.data().pool;
+    let latest_cached =
+        get_latest_lastfm_scrobble_time(pool, lastfm.service_key(), cache_key.clone()).await;
+
     let message = ctx
         .send(get_loading_message(username.clone(), None))
         .await
         .unwrap();
 
-    let user = get_lastfm_client(username.clone(), api.clone()).await;
-
-    let recent_tracks = user.recent_tracks(lfm_range.start, lfm_range.end).await;
-    let ret: Option<Vec<RecordedTrack>>;
+    let synced: Option<usize>;
     tokio::select! {
-        stream_vec = get_streams(recent_tracks, message.clone(), ctx, username) => {
-            ret = stream_vec;
+        stored = get_streams(lastfm, latest_cached.map(|x| x + 1), message.clone(), ctx, username) => {
+            synced = stored;
         },
         _ = message.message().await.unwrap()
         .await_component_interaction(ctx)
         .author_id(ctx.author().id)
         .custom_ids(vec!["cancel".to_string()]).into_future() => {
-            ret = None;
+            synced = None;
         }
     }
 
@@ -103,28 +123,65 @@ pub async fn get_lastfm_user(
     // this is unlikely but there's no reason to crash the entire command for that
     let _ = message.delete(ctx).await;
 
-    ret
+    match synced {
+        Some(_) => {
+            record_lastfm_sync_success(pool, lastfm.service_key(), cache_key, unix_now()).await;
+            true
+        }
+        None => {
+            // user not found, the request failed, or the user cancelled. pages stored before that are kept,
+            // they're the oldest ones so the next sync carries on after them, but a cold cache isn't a whole answer
+            if latest_cached.is_none() {
+                return false;
+            }
+            log::warn!(
+                "Couldn't sync Last.FM user {}, serving cached scrobbles",
+                cache_key
+            );
+            true
+        }
+    }
 }
 
-pub async fn lfmuser_cmd(ctx: Context<'_>, api: Option<String>, arg: String) {
+/// Brings the scrobble cache for `username` up to date, then reads the requested range out of it.
+pub async fn get_lastfm_user(
+    ctx: Context<'_>,
+    lastfm: LastFMApi,
+    username: String,
+    lfm_range: LfmRange,
+) -> Option<Vec<LastFMScrobble>> {
+    if !sync_lastfm_cache(ctx, &lastfm, username.clone()).await {
+        return None;
+    }
+    Some(
+        get_lastfm_scrobbles(
+            &ctx.data().pool,
+            lastfm.service_key(),
+            username.to_lowercase(),
+            lfm_range.start,
+            lfm_range.end,
+        )
+        .await,
+    )
+}
+
+/// Looks up any Last.FM user by name. Nobody linked them, so nothing about them is cached,
+/// Last.FM counts the scrobbles itself.
+pub async fn lfmuser_cmd(ctx: Context<'_>, api: Option<LastFMApi>, arg: String) {
     match api {
         Some(lastfm_api) => {
-            let now_secs = SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .map(|x| x.as_secs() as i64)
-                .ok();
-            let one_year_ago = now_secs.map(|x| x - 31_536_000);
-            let tracks = get_lastfm_user(
-                ctx,
-                lastfm_api,
-                arg.clone(),
-                LfmRange::new(one_year_ago, now_secs),
-            )
-            .await;
-
-            let trackcount = match tracks {
-                Some(tracks) => tracks.len().to_string(),
-                None => "[user not found, or cancel occurred]".to_string(),
+            let tz = ctx.data().timezone(format_user(ctx.author().clone())).await;
+            let bounds = StatsRange::Year.bounds(tz);
+            let trackcount = match lastfm_api
+                .user_get_recent_tracks(arg.clone(), bounds.start, bounds.end, 1, 1)
+                .await
+            {
+                Ok(recent) => recent.attr.total,
+                Err(err) if err.not_found() => "[user not found]".to_string(),
+                Err(err) => {
+                    log::error!("Error getting Last.FM user {}: {}", arg, err);
+                    "[couldn't reach Last.FM]".to_string()
+                }
             };
 
             ctx.channel_id()
@@ -133,7 +190,7 @@ pub async fn lfmuser_cmd(ctx: Context<'_>, api: Option<String>, arg: String) {
                     CreateMessage::new().embed(
                         CreateEmbed::new()
                             .title(format!("LastFM user {}'s scrobbles", arg.clone()))
-                            .field("Within the past year", trackcount, false),
+                            .field("This year", trackcount, false),
                     ),
                 )
                 .await
@@ -146,3 +203,75 @@ pub async fn lfmuser_cmd(ctx: Context<'_>, api: Option<String>, arg: String) {
         }
     }
 }
+
+pub async fn sync_cmd(ctx: Context<'_>, pool: &PgPool, formatted_user: String) {
+    let mut assigned_username: Option<String> = None;
+    let mut service: Option<String> = None;
+    for result in get_lastfm_username(pool, formatted_user).await {
+        assigned_username = result.lastfm_username;
+        service = result.service;
+    }
+
+    let Some(username) = assigned_username else {
+        ctx.say("You don't have a Last.FM username set up.")
+            .await
+            .unwrap();
+        return;
+    };
+    let Some(lastfm) = ctx.data().lastfm.clone() else {
+        ctx.say("The bot owner has not set up a Last.FM API key.")
+            .await
+            .unwrap();
+        return;
+    };
+    let lastfm = lastfm.for_service(service);
+
+    let cache_key = username.to_lowercase();
+    let cached = count_lastfm_scrobbles(pool, lastfm.service_key(), cache_key.clone()).await;
+    let status = get_lastfm_sync_status(pool, lastfm.service_key(), cache_key).await;
+
+    let mut embed = CreateEmbed::new()
+        .title(format!(
+            "Sync status for {} user {}",
+            lastfm.service_name(),
+            username
+        ))
+        .field("Cached scrobbles", cached.to_string(), false);
+
+    match status.first() {
+        Some(status) => {
+            embed = embed.field(
+                "Last synced",
+                status
+                    .last_synced
+                    .map(|x| format!("<t:{}:R>", x))
+                    .unwrap_or("Never".to_string()),
+                false,
+            );
+            let failures = status.failures.unwrap_or(0);
+            if failures > 0 {
+                embed = embed
+                    .field("Failed attempts", failures.to_string(), true)
+                    .field(
+                        "Last error",
+                        status.last_error.clone().unwrap_or("[none]".to_string()),
+                        true,
+                    );
+                if let Some(next) = next_attempt(status) {
+                    embed = embed.field("Next attempt", format!("<t:{}:R>", next), false);
+                }
+            }
+        }
+        None => {
+            embed = embed.field("Last synced", "Never, it's queued for the next pass", false);
+        }
+    }
+
+    let metrics = lastfm.limiter.metrics();
+    embed = embed.footer(CreateEmbedFooter::new(format!(
+        "Last.FM queue: {} waiting, {} rate limited responses",
+        metrics.queue_depth, metrics.throttled
+    )));
+
+    ctx.send(CreateReply::default().embed(embed)).await.unwrap();
+}
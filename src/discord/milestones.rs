@@ -0,0 +1,197 @@
+use super::bot::{format_user, Context, MljboardUser};
+use super::lastfm::sync_lastfm_cache;
+use crate::db::postgres::{
+    count_lastfm_scrobbles, get_milestone_setting, get_nth_lastfm_scrobble, set_milestone_total,
+};
+use crate::history::streaks::local_date;
+use crate::history::{history_in_range, BackendError, HistoryEntry};
+use crate::range::StatsRange;
+use chrono_tz::Tz;
+use poise::CreateReply;
+use reqwest::Client;
+use serenity::all::{ChannelId, CreateEmbed, CreateMessage, Http, Message, User, UserId};
+use sqlx::PgPool;
+
+/// Totals under this are announced every thousand, bigger ones every ten thousand.
+const SMALL_MILESTONES_UNTIL: u64 = 10_000;
+
+/// The biggest round number of scrobbles `total` has reached, 0 before the first one.
+pub fn milestone_for(total: u64) -> u64 {
+    let step = match total < SMALL_MILESTONES_UNTIL {
+        true => 1_000,
+        false => 10_000,
+    };
+    total - total % step
+}
+
+/// Remembers `total` for `formatted_user`, and tells them where they asked to be told if it crossed a round number.
+/// Only does anything for members who turned announcements on. Besides commands that count someone's scrobbles,
+/// the Last.FM sync worker calls this, so members hear about milestones without asking for their count.
+/// `user_id` is who to tell, the one saved with the setting is used without it.
+pub async fn check_milestone(
+    http: &Http,
+    pool: &PgPool,
+    formatted_user: String,
+    user_id: Option<UserId>,
+    total: u64,
+) {
+    let Some(setting) = get_milestone_setting(pool, formatted_user.clone())
+        .await
+        .into_iter()
+        .next()
+    else {
+        return;
+    };
+    let last_total = setting.last_total.map(|x| x.max(0) as u64);
+    // totals only go up, a smaller one is a backend running behind
+    if last_total.is_some_and(|x| x >= total) {
+        return;
+    }
+    set_milestone_total(pool, formatted_user.clone(), total as i64).await;
+
+    // the first total seen is only the starting point
+    let Some(last_total) = last_total else {
+        return;
+    };
+    let milestone = milestone_for(total);
+    if milestone == 0 || milestone <= milestone_for(last_total) {
+        return;
+    }
+    let Some(user_id) = user_id.or(setting.user_id.map(|x| UserId::new(x as u64))) else {
+        return;
+    };
+
+    let message = CreateMessage::new().content(format!(
+        "🎉 <@{}> just passed **{}** scrobbles!",
+        user_id, milestone
+    ));
+    let sent = match (setting.announce.as_deref(), setting.channel_id) {
+        (Some("dm"), _) => match user_id.create_dm_channel(http).await {
+            Ok(channel) => channel.send_message(http, message).await.map(|_| ()),
+            Err(err) => Err(err),
+        },
+        (Some("channel"), Some(channel_id)) => ChannelId::new(channel_id as u64)
+            .send_message(http, message)
+            .await
+            .map(|_| ()),
+        _ => Ok(()),
+    };
+    if let Err(err) = sent {
+        log::error!(
+            "Error announcing a milestone for {}: {}",
+            formatted_user,
+            err
+        );
+    }
+}
+
+/// How many scrobbles `user` has and their `n`th one, counting from 1 at the oldest.
+/// Last.FM's come out of the cache, so the whole history doesn't have to be loaded and sorted.
+async fn nth_scrobble(
+    client: Client,
+    ctx: Context<'_>,
+    user: &MljboardUser,
+    n: u64,
+    tz: Option<Tz>,
+) -> Result<(u64, Option<HistoryEntry>), BackendError> {
+    if let MljboardUser::LastFMUser(lfm) = user {
+        let lastfm = ctx.data().lastfm_for(lfm);
+        if !sync_lastfm_cache(ctx, &lastfm, lfm.username.clone()).await {
+            return Err(BackendError::LastFMSync);
+        }
+        let pool = &ctx.data().pool;
+        let cache_key = lfm.username.to_lowercase();
+        let total = count_lastfm_scrobbles(pool, lastfm.service_key(), cache_key.clone())
+            .await
+            .max(0) as u64;
+        if n == 0 || n > total {
+            return Ok((total, None));
+        }
+        let entry = get_nth_lastfm_scrobble(pool, lastfm.service_key(), cache_key, n as i64 - 1)
+            .await
+            .into_iter()
+            .next()
+            .map(|scrobble| HistoryEntry {
+                timestamp: scrobble.scrobbled_at,
+                artist: scrobble.artist.unwrap_or_default(),
+                track: scrobble.track.unwrap_or_default(),
+                album: scrobble.album.filter(|x| !x.is_empty()),
+                url: scrobble.url.filter(|x| !x.is_empty()),
+                art: None,
+                duration: None,
+            });
+        return Ok((total, entry));
+    }
+
+    let mut entries = history_in_range(client, ctx, user, &StatsRange::AllTime, tz)
+        .await?
+        .into_iter()
+        .filter(|entry| entry.timestamp.is_some())
+        .collect::<Vec<_>>();
+    let total = entries.len() as u64;
+    if n == 0 || n > total {
+        return Ok((total, None));
+    }
+    entries.sort_by_key(|entry| entry.timestamp);
+    Ok((total, entries.into_iter().nth(n as usize - 1)))
+}
+
+pub async fn milestone_cmd(
+    client: Client,
+    user: Option<MljboardUser>,
+    _msg: Option<Message>,
+    ctx: Context<'_>,
+    n: u64,
+    tz: Option<Tz>,
+    target: User,
+) {
+    if let Some(user) = user {
+        ctx.defer().await.unwrap();
+
+        let (total, entry) = match nth_scrobble(client, ctx, &user, n, tz).await {
+            Ok(found) => found,
+            Err(err) => {
+                log::error!("Error getting history for milestone: {}", err);
+                ctx.reply("There was an error getting your scrobbles.")
+                    .await
+                    .unwrap();
+                return;
+            }
+        };
+        check_milestone(
+            ctx.http(),
+            &ctx.data().pool,
+            format_user(target.clone()),
+            Some(target.id),
+            total,
+        )
+        .await;
+        let Some(entry) = entry else {
+            ctx.reply(format!(
+                "{} has {} scrobbles, pick a number from 1 to {}.",
+                target.name, total, total
+            ))
+            .await
+            .unwrap();
+            return;
+        };
+
+        let date = entry
+            .timestamp
+            .and_then(|timestamp| local_date(timestamp, tz))
+            .map_or("Unknown".to_string(), |date| date.to_string());
+
+        let mut embed = CreateEmbed::new()
+            .title(format!("{}'s scrobble #{}", target.name, n))
+            .field("Track", entry.track.clone(), true)
+            .field("Artist", entry.artist.clone(), true)
+            .field("Date", date, true);
+        if let Some(album) = &entry.album {
+            embed = embed.field("Album", album.clone(), true);
+        }
+        if let Some(url) = &entry.url {
+            embed = embed.url(url);
+        }
+        ctx.send(CreateReply::default().embed(embed)).await.unwrap();
+    }
+}
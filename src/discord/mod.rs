@@ -1,5 +1,10 @@
+pub mod autocomplete;
 pub mod bot;
+pub mod choose;
+pub mod guild;
 pub mod lastfm;
 pub mod macros;
+pub mod milestones;
 pub mod ops;
+pub mod paginate;
 pub mod setups;
@@ -1,66 +1,86 @@
-use super::bot::{Context, MljboardUser};
-use crate::discord::lastfm::get_lastfm_user;
-use crate::discord::lastfm::LfmRange;
-use image::ImageBuffer;
-use image::RgbaImage;
+use super::bot::{format_user, Context, MljboardUser};
+use super::choose::choose;
+use super::guild::{crown_change_message, update_crown};
+use super::milestones::check_milestone;
+use crate::charts::{
+    chart_art, chart_from_history, find_entries, get_chart, ChartEntry, ChartKind,
+};
+use crate::discord::lastfm::{get_lastfm_user, LastFMUser};
+use crate::discord::paginate::{paginate, paginate_with_attachments};
+use crate::history::activity::weekday_hour_counts;
+use crate::history::streaks::{daily_counts, render_calendar, streaks};
+use crate::history::{fetch_art, history_in_range, recent_history, HistoryEntry};
+use crate::images::grid::render_grid;
+use crate::images::heatmap::render_heatmap;
+use crate::normalize::{artist_key, credits, normalize, split_artists};
+use crate::range::{today, StatsRange, MONTH_NAMES};
+use chrono::{DateTime, Datelike, Utc};
+use chrono_tz::Tz;
+use mljcl::credentials::MalojaCredentials;
 use mljcl::history::numscrobbles_async;
 use mljcl::range::Range as MljRange;
-use poise::CreateReply;
+use poise::{ChoiceParameter, CreateReply};
 use reqwest::Client;
-use serenity::all::{CreateAttachment, CreateEmbed, CreateMessage, Message};
-use std::io::Cursor;
-use std::time::SystemTime;
-
-use image::imageops::FilterType;
-use image::DynamicImage;
+use serenity::all::{
+    CreateAttachment, CreateEmbed, CreateEmbedAuthor, CreateEmbedFooter, CreateMessage, Message,
+    User,
+};
+use std::collections::HashSet;
 
 pub enum GetScrobbleCountFailed {
     UserNotFound,
+    ArtistNotFound,
+    LastFMError,
     CancelOccurred,
     MalojaError,
+    ListenBrainzError,
 }
 
 impl ToString for GetScrobbleCountFailed {
     fn to_string(&self) -> String {
         match self {
             GetScrobbleCountFailed::UserNotFound => "Last.FM user not found".to_string(),
+            GetScrobbleCountFailed::ArtistNotFound => "Artist not found on Last.FM".to_string(),
+            GetScrobbleCountFailed::LastFMError => "Couldn't reach Last.FM".to_string(),
             GetScrobbleCountFailed::CancelOccurred => "Cancel occurred".to_string(),
             GetScrobbleCountFailed::MalojaError => "Maloja error".to_string(),
+            GetScrobbleCountFailed::ListenBrainzError => "ListenBrainz error".to_string(),
         }
     }
 }
 
+/// How many scrobbles `user` has in `range`, only of `artist` if it's given.
+/// For Maloja users `artist` has to be the name Maloja knows, see `resolve_artist`.
 pub async fn get_scrobble_count(
     client: Client,
     user: MljboardUser,
     _msg: Option<Message>,
     ctx: Context<'_>,
     artist: Option<String>,
-    maloja_range: MljRange,
-    lfm_range: LfmRange,
+    range: &StatsRange,
+    tz: Option<Tz>,
 ) -> Result<u64, GetScrobbleCountFailed> {
+    let lfm_range = range.bounds(tz);
     match user {
         MljboardUser::LastFMUser(lfm) => {
-            if !lfm_range.available() {
-                // we shouldn't use the `lastfm` crate here since it only gets individual tracks (which takes a while), we need to use my lib to get user summary
-                lfm_stats::user_get_info(
-                    lfm.username,
-                    ctx.data()
-                        .lastfm_api
-                        .clone()
-                        .expect("No Last.FM API key passed"),
-                )
-                .await
-                .map(|x| x.scrobbles)
-                .map_err(|_| GetScrobbleCountFailed::UserNotFound)
+            if !lfm_range.available() && artist.is_some() {
+                lastfm_artist_playcount(ctx, &lfm, artist.unwrap()).await
+            } else if !lfm_range.available() {
+                // no need to go through the history, the user summary has the total
+                ctx.data()
+                    .lastfm_for(&lfm)
+                    .user_get_info(lfm.username)
+                    .await
+                    .map(|x| x.playcount.parse::<u64>().unwrap_or(0))
+                    .map_err(|err| match err.not_found() {
+                        true => GetScrobbleCountFailed::UserNotFound,
+                        false => GetScrobbleCountFailed::LastFMError,
+                    })
             } else {
                 let mut ret = 0;
                 let tracks = get_lastfm_user(
                     ctx,
-                    ctx.data()
-                        .lastfm_api
-                        .clone()
-                        .expect("No Last.FM API key passed"),
+                    ctx.data().lastfm_for(&lfm),
                     lfm.username.clone(),
                     lfm_range,
                 )
@@ -69,7 +89,7 @@ pub async fn get_scrobble_count(
                     Some(tracks) => {
                         if let Some(artist) = artist {
                             for track in tracks {
-                                if track.artist.name.to_uppercase() == artist.to_uppercase() {
+                                if track.artist.is_some_and(|x| credits(&x, &artist, false)) {
                                     ret += 1;
                                 }
                             }
@@ -83,14 +103,123 @@ pub async fn get_scrobble_count(
                 }
             }
         }
-        MljboardUser::MalojaUser(creds) => {
-            numscrobbles_async(artist, maloja_range, creds.clone(), client.clone())
-                .await
-                .map_err(|_| GetScrobbleCountFailed::MalojaError)
+        MljboardUser::MalojaUser(creds) => match range.maloja(tz) {
+            Some(maloja_range) => {
+                numscrobbles_async(artist, maloja_range, creds.clone(), client.clone())
+                    .await
+                    .map_err(|_| GetScrobbleCountFailed::MalojaError)
+            }
+            None => {
+                // Maloja can't count custom ranges or Monday-based weeks itself, go through the scrobbles instead
+                let user = MljboardUser::MalojaUser(creds);
+                history_in_range(client, ctx, &user, range, tz)
+                    .await
+                    .map(|entries| {
+                        entries
+                            .into_iter()
+                            .filter(|entry| {
+                                artist
+                                    .as_ref()
+                                    .map_or(true, |artist| credits(&entry.artist, artist, true))
+                            })
+                            .count() as u64
+                    })
+                    .map_err(|_| GetScrobbleCountFailed::MalojaError)
+            }
+        },
+        MljboardUser::ListenBrainzUser(lb) => {
+            let listenbrainz = &ctx.data().listenbrainz;
+            // ListenBrainz ranges are timestamps, same as Last.FM's
+            match (lfm_range.available(), artist) {
+                (false, None) => listenbrainz.listen_count(lb.username).await,
+                (false, Some(artist)) => {
+                    listenbrainz.artist_listen_count(lb.username, artist).await
+                }
+                (true, artist) => listenbrainz
+                    .listens(lb.username, lfm_range.start, lfm_range.end)
+                    .await
+                    .map(|listens| {
+                        listens
+                            .into_iter()
+                            .filter(|listen| {
+                                artist.as_ref().map_or(true, |artist| {
+                                    credits(&listen.track_metadata.artist_name, artist, false)
+                                })
+                            })
+                            .count() as u64
+                    }),
+            }
+            .map_err(|_| GetScrobbleCountFailed::ListenBrainzError)
+        }
+    }
+}
+
+/// Last.FM keeps a per-user count on the artist itself, no need to go through the history.
+async fn lastfm_artist_playcount(
+    ctx: Context<'_>,
+    lfm: &LastFMUser,
+    artist: String,
+) -> Result<u64, GetScrobbleCountFailed> {
+    ctx.data()
+        .lastfm_for(lfm)
+        .artist_get_user_playcount(artist, lfm.username.clone())
+        .await
+        .map_err(|err| match err.not_found() {
+            true => GetScrobbleCountFailed::ArtistNotFound,
+            false => GetScrobbleCountFailed::LastFMError,
+        })
+}
+
+/// All-time scrobbles of `artist` for a member other than the caller, e.g. on a leaderboard.
+/// Nothing is posted and nobody's Last.FM history is synced, Last.FM's own count is used instead.
+pub async fn member_artist_count(
+    client: Client,
+    ctx: Context<'_>,
+    user: MljboardUser,
+    artist: String,
+) -> Result<u64, GetScrobbleCountFailed> {
+    match &user {
+        MljboardUser::LastFMUser(lfm) => lastfm_artist_playcount(ctx, lfm, artist).await,
+        _ => {
+            let artist = resolve_artist(client.clone(), &user, artist).await;
+            get_scrobble_count(
+                client,
+                user,
+                None,
+                ctx,
+                Some(artist),
+                &StatsRange::AllTime,
+                None,
+            )
+            .await
         }
     }
 }
 
+/// The name `user`'s backend knows `artist` by, for `get_scrobble_count`. Only Maloja needs it,
+/// and it goes through the member's whole artist chart, so look it up once per command and member.
+pub async fn resolve_artist(client: Client, user: &MljboardUser, artist: String) -> String {
+    match user {
+        MljboardUser::MalojaUser(creds) => maloja_artist_name(client, creds, artist).await,
+        _ => artist,
+    }
+}
+
+/// The name Maloja knows `artist` by. Maloja only matches artist names exactly,
+/// so look the artist up in the user's chart the same way every other backend is matched.
+async fn maloja_artist_name(client: Client, creds: &MalojaCredentials, artist: String) -> String {
+    let key = artist_key(&artist);
+    match mljcl::charts::charts_artists_async(MljRange::AllTime, creds.clone(), client).await {
+        Ok(chart) => chart
+            .artists
+            .into_iter()
+            .map(|(chart_artist, _)| chart_artist.name)
+            .find(|name| normalize(name) == key)
+            .unwrap_or(artist),
+        Err(_) => artist,
+    }
+}
+
 pub fn human_readable_result(result: Result<u64, GetScrobbleCountFailed>) -> String {
     match result {
         Ok(count) => count.to_string(),
@@ -98,30 +227,80 @@ pub fn human_readable_result(result: Result<u64, GetScrobbleCountFailed>) -> Str
     }
 }
 
+/// Marks embeds about users who proved they own their Last.FM account.
+pub fn with_verified_footer(embed: CreateEmbed, user: &MljboardUser) -> CreateEmbed {
+    match user {
+        MljboardUser::LastFMUser(lfm) if lfm.verified => {
+            embed.footer(CreateEmbedFooter::new("✅ Verified Last.FM account"))
+        }
+        _ => embed,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn artistscrobbles_cmd(
     client: Client,
     user: Option<MljboardUser>,
     msg: Option<Message>,
     ctx: Context<'_>,
     arg: String,
+    range: Option<StatsRange>,
+    tz: Option<Tz>,
+    target: User,
 ) {
     if let Some(user) = user {
-        let all_time_scrobbles: String = human_readable_result(
-            get_scrobble_count(
-                client,
-                user,
-                msg.clone(),
-                ctx,
-                Some(arg.clone()),
-                MljRange::AllTime,
-                LfmRange::new(None, None),
-            )
-            .await,
-        );
+        let artist = resolve_artist(client.clone(), &user, arg.clone()).await;
+        let all_time_result = get_scrobble_count(
+            client.clone(),
+            user.clone(),
+            msg.clone(),
+            ctx,
+            Some(artist.clone()),
+            &StatsRange::AllTime,
+            tz,
+        )
+        .await;
 
-        let embed = CreateEmbed::new()
-            .title(format!("{}'s scrobbles for {}", ctx.author().name, arg))
+        // only members others can look up take part in crowns
+        let mut crown_change = None;
+        if let (Ok(count), Some(guild_id)) = (&all_time_result, ctx.guild_id()) {
+            if ctx.data().is_public(format_user(target.clone())).await {
+                crown_change = update_crown(
+                    ctx,
+                    guild_id.get() as i64,
+                    &arg,
+                    target.id,
+                    format_user(target.clone()),
+                    *count,
+                    &[],
+                )
+                .await;
+            }
+        }
+        let all_time_scrobbles: String = human_readable_result(all_time_result);
+
+        let mut embed = CreateEmbed::new()
+            .title(format!("{}'s scrobbles for {}", target.name, arg))
             .field("All time", all_time_scrobbles.to_string(), false);
+        if let Some(change) = crown_change {
+            embed = embed.description(crown_change_message(&change, target.id, &arg));
+        }
+        if let Some(range) = range.filter(|x| *x != StatsRange::AllTime) {
+            let range_scrobbles = human_readable_result(
+                get_scrobble_count(
+                    client,
+                    user.clone(),
+                    msg.clone(),
+                    ctx,
+                    Some(artist),
+                    &range,
+                    tz,
+                )
+                .await,
+            );
+            embed = embed.field(range.to_string(), range_scrobbles, false);
+        }
+        let embed = with_verified_footer(embed, &user);
         match msg {
             Some(msg) => {
                 msg.channel_id
@@ -136,50 +315,230 @@ pub async fn artistscrobbles_cmd(
     }
 }
 
-pub async fn scrobbles_cmd(
+/// How far down a library `/trackscrobbles` and `/albumscrobbles` look for matches.
+pub const LIBRARY_LOOKUP_SIZE: usize = 1000;
+
+/// Finds the album or track the caller meant, asking them to pick when several match.
+async fn find_library_entry(
+    client: Client,
+    ctx: Context<'_>,
+    user: &MljboardUser,
+    kind: ChartKind,
+    artist: &str,
+    name: &str,
+) -> Option<ChartEntry> {
+    let what = match kind {
+        ChartKind::Albums => "album",
+        _ => "track",
+    };
+    let library = match get_chart(
+        client,
+        ctx,
+        user,
+        kind,
+        &StatsRange::AllTime,
+        None,
+        LIBRARY_LOOKUP_SIZE,
+    )
+    .await
+    {
+        Ok(library) => library,
+        Err(err) => {
+            log::error!("Error getting {} chart: {}", what, err);
+            ctx.reply(format!("There was an error getting your {}s.", what))
+                .await
+                .unwrap();
+            return None;
+        }
+    };
+
+    let mut matches = find_entries(&library, artist, name, user.comma_joined_credits());
+    if matches.is_empty() {
+        if let MljboardUser::LastFMUser(lfm) = user {
+            // Last.FM only hands out the top of a library, ask about this one directly
+            let lastfm = ctx.data().lastfm_for(lfm);
+            let count = match kind {
+                ChartKind::Albums => {
+                    lastfm
+                        .album_get_user_playcount(
+                            artist.to_string(),
+                            name.to_string(),
+                            lfm.username.clone(),
+                        )
+                        .await
+                }
+                _ => {
+                    lastfm
+                        .track_get_user_playcount(
+                            artist.to_string(),
+                            name.to_string(),
+                            lfm.username.clone(),
+                        )
+                        .await
+                }
+            };
+            if let Ok(count) =
+                count.map_err(|err| log::error!("Error getting {} playcount: {}", what, err))
+            {
+                matches.push(ChartEntry {
+                    name: name.to_string(),
+                    artist: Some(artist.to_string()),
+                    count,
+                    url: None,
+                    art: None,
+                });
+            }
+        }
+    }
+
+    match matches.len() {
+        0 => {
+            ctx.reply(format!(
+                "Couldn't find a {} called {} by {}.",
+                what, name, artist
+            ))
+            .await
+            .unwrap();
+            None
+        }
+        1 => matches.pop(),
+        _ => {
+            let options = matches
+                .iter()
+                .map(|entry| {
+                    format!(
+                        "{} by {}",
+                        entry.name,
+                        entry.artist.clone().unwrap_or_default()
+                    )
+                })
+                .collect::<Vec<String>>();
+            choose(
+                ctx,
+                format!("More than one {} matches, which did you mean?", what),
+                &options,
+            )
+            .await
+            .map(|index| matches.swap_remove(index))
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn itemscrobbles_cmd(
     client: Client,
     user: Option<MljboardUser>,
-    msg: Option<Message>,
+    _msg: Option<Message>,
     ctx: Context<'_>,
+    kind: ChartKind,
+    artist: String,
+    name: String,
+    range: Option<StatsRange>,
+    tz: Option<Tz>,
+    target: User,
 ) {
-    let this_year_scrobbles: String; // if we fail to get scrobbles, we need an error message
-    let all_time_scrobbles: String;
     if let Some(user) = user {
-        let now_secs = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .map(|x| x.as_secs() as i64)
-            .ok();
-        let one_year_ago = now_secs.map(|x| x - 31_536_000);
+        ctx.defer().await.unwrap();
 
-        this_year_scrobbles = human_readable_result(
-            get_scrobble_count(
+        let Some(entry) =
+            find_library_entry(client.clone(), ctx, &user, kind, &artist, &name).await
+        else {
+            return;
+        };
+        let credit = entry.artist.clone().unwrap_or_default();
+
+        let mut embed = CreateEmbed::new()
+            .title(format!(
+                "{}'s scrobbles for {} by {}",
+                target.name, entry.name, credit
+            ))
+            .field("All time", entry.count.to_string(), false);
+        if let Some(url) = &entry.url {
+            embed = embed.url(url);
+        }
+        if let Some(range) = range.filter(|x| *x != StatsRange::AllTime) {
+            let range_scrobbles = match get_chart(
                 client.clone(),
-                user.clone(),
-                msg.clone(),
                 ctx,
-                None,
-                MljRange::In("thisyear".to_string()),
-                LfmRange::new(one_year_ago, now_secs),
+                &user,
+                kind,
+                &range,
+                tz,
+                LIBRARY_LOOKUP_SIZE,
             )
-            .await,
-        );
-        all_time_scrobbles = human_readable_result(
-            get_scrobble_count(
-                client,
-                user,
-                msg.clone(),
-                ctx,
-                None,
-                MljRange::AllTime,
-                LfmRange::new(None, None),
+            .await
+            {
+                Ok(chart) => {
+                    find_entries(&chart, &credit, &entry.name, user.comma_joined_credits())
+                        .into_iter()
+                        .find(|x| normalize(&x.name) == normalize(&entry.name))
+                        .map_or(0, |x| x.count)
+                        .to_string()
+                }
+                Err(err) => {
+                    log::error!("Error getting chart for {}: {}", range, err);
+                    "Couldn't get scrobbles for this range".to_string()
+                }
+            };
+            embed = embed.field(range.to_string(), range_scrobbles, false);
+        }
+        let mut embed = with_verified_footer(embed, &user);
+
+        let mut reply = CreateReply::default();
+        if kind == ChartKind::Albums {
+            if let Some(art) = chart_art(client, ctx, &user, &entry).await {
+                reply = reply.attachment(CreateAttachment::bytes(art, "art.png"));
+                embed = embed.thumbnail("attachment://art.png");
+            }
+        }
+        ctx.send(reply.embed(embed)).await.unwrap();
+    }
+}
+
+pub async fn scrobbles_cmd(
+    client: Client,
+    user: Option<MljboardUser>,
+    msg: Option<Message>,
+    ctx: Context<'_>,
+    range: StatsRange,
+    tz: Option<Tz>,
+    target: User,
+) {
+    let range_scrobbles: String; // if we fail to get scrobbles, we need an error message
+    let all_time_scrobbles: String;
+    if let Some(user) = user {
+        let all_time_result = get_scrobble_count(
+            client.clone(),
+            user.clone(),
+            msg.clone(),
+            ctx,
+            None,
+            &StatsRange::AllTime,
+            tz,
+        )
+        .await;
+        if let Ok(total) = &all_time_result {
+            check_milestone(
+                ctx.http(),
+                &ctx.data().pool,
+                format_user(target.clone()),
+                Some(target.id),
+                *total,
             )
-            .await,
-        );
+            .await;
+        }
+        all_time_scrobbles = human_readable_result(all_time_result);
 
-        let embed = CreateEmbed::new()
-            .title(format!("{}'s scrobbles", ctx.author().name))
-            .field("All time", all_time_scrobbles.to_string(), false)
-            .field("This year", this_year_scrobbles.to_string(), false);
+        let mut embed = CreateEmbed::new()
+            .title(format!("{}'s scrobbles", target.name))
+            .field("All time", all_time_scrobbles.to_string(), false);
+        if range != StatsRange::AllTime {
+            range_scrobbles = human_readable_result(
+                get_scrobble_count(client, user.clone(), msg.clone(), ctx, None, &range, tz).await,
+            );
+            embed = embed.field(range.to_string(), range_scrobbles.to_string(), false);
+        }
+        let embed = with_verified_footer(embed, &user);
         match msg {
             Some(msg) => {
                 msg.channel_id
@@ -194,98 +553,912 @@ pub async fn scrobbles_cmd(
     }
 }
 
+pub async fn send_grid(
+    ctx: Context<'_>,
+    album_arts: Vec<Vec<u8>>,
+    square_size: usize,
+    title: String,
+) {
+    let grid_image_bytes = render_grid(album_arts, square_size);
+    let attachment = CreateAttachment::bytes(grid_image_bytes, "grid.png");
+
+    let message = CreateReply::default()
+        .attachment(attachment)
+        .embed(CreateEmbed::new().title(title).attachment("grid.png"));
+
+    ctx.send(message).await.unwrap();
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn grid_cmd(
     client: Client,
     user: Option<MljboardUser>,
     _msg: Option<Message>,
     ctx: Context<'_>,
     square_size: usize,
-    range: mljcl::range::Range,
+    range: StatsRange,
+    tz: Option<Tz>,
+    target: User,
 ) {
     let album_count = square_size.pow(2);
 
     if let Some(user) = user {
-        match user {
-            MljboardUser::MalojaUser(user) => {
-                ctx.defer().await.unwrap(); // Apparently needed for size > 1 because requests simply take too long
+        ctx.defer().await.unwrap(); // Apparently needed for size > 1 because requests simply take too long
 
-                let albums_ranked =
-                    mljcl::charts::charts_albums_async(range, None, user.clone(), client.clone())
-                        .await
-                        .map(|x| x.albums);
-                if let Ok(mut albums_ranked) = albums_ranked {
-                    albums_ranked.truncate(album_count);
-                    let top_album_ids: Vec<String> = albums_ranked
-                        .into_iter()
-                        .map(|(album, _)| album.id)
-                        .collect();
-
-                    let image_width = 64;
-                    let image_height = 64;
-
-                    let mut grid_image: RgbaImage = ImageBuffer::new(
-                        image_width * square_size as u32,
-                        image_height * square_size as u32,
-                    );
-
-                    let mut x = 0;
-                    let mut y = 0;
-
-                    let mut image_count = 0;
-
-                    for album_id in top_album_ids {
-                        let album_art_bytes =
-                            mljcl::art::album_art_async(album_id, user.clone(), client.clone())
-                                .await
-                                .unwrap();
-                        if let Ok(img) = image::load_from_memory(&album_art_bytes) {
-                            let mut image = DynamicImage::ImageRgba8(image::imageops::resize(
-                                &img,
-                                image_width,
-                                image_height,
-                                FilterType::CatmullRom,
-                            ));
-                            image::imageops::overlay(
-                                &mut grid_image,
-                                image.as_mut_rgba8().unwrap(),
-                                x,
-                                y,
-                            );
-                            image_count += 1;
-                            x += 64;
-                            if image_count >= square_size {
-                                x = 0;
-                                y += 64;
-                                image_count = 0;
-                            }
-                        }
+        match get_chart(
+            client.clone(),
+            ctx,
+            &user,
+            ChartKind::Albums,
+            &range,
+            tz,
+            album_count,
+        )
+        .await
+        {
+            Ok(albums) => {
+                // missing art is left out, same as art Maloja can't serve
+                let mut album_arts = vec![];
+                for album in albums.iter() {
+                    if let Some(art) = chart_art(client.clone(), ctx, &user, album).await {
+                        album_arts.push(art);
                     }
+                }
 
-                    let mut grid_image_bytes: Vec<u8> = Vec::new();
-                    grid_image
-                        .write_to(
-                            &mut Cursor::new(&mut grid_image_bytes),
-                            image::ImageOutputFormat::Png,
-                        )
-                        .unwrap();
-                    let attachment = CreateAttachment::bytes(grid_image_bytes, "grid.png");
+                let title = format!("{}'s top albums ({})", target.name, range);
+                send_grid(ctx, album_arts, square_size, title).await;
+            }
+            Err(err) => {
+                log::error!("Error getting album chart for grid: {}", err);
+                ctx.reply("There was an error getting your album chart.")
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+}
 
-                    let message = CreateReply::default()
-                        .attachment(attachment)
-                        .embed(CreateEmbed::new().attachment("grid.png"));
+pub async fn np_cmd(
+    client: Client,
+    user: Option<MljboardUser>,
+    _msg: Option<Message>,
+    ctx: Context<'_>,
+    target: User,
+) {
+    if let Some(user) = user {
+        let entry = match recent_history(client.clone(), ctx, &user, 1).await {
+            Ok(history) => history.into_iter().next(),
+            Err(err) => {
+                log::error!("Error getting recent history for {}: {}", target.name, err);
+                ctx.reply("There was an error getting your recent tracks.")
+                    .await
+                    .unwrap();
+                return;
+            }
+        };
+        let Some(entry) = entry else {
+            ctx.reply(format!("{} hasn't scrobbled anything yet.", target.name))
+                .await
+                .unwrap();
+            return;
+        };
 
-                    ctx.send(message).await.unwrap();
-                } else {
-                    ctx.reply("There was an error getting your album chart.")
-                        .await
-                        .unwrap();
-                }
+        let (artist_plays, track_plays) =
+            get_track_playcounts(client.clone(), ctx, &user, &entry).await;
+
+        let mut embed = CreateEmbed::new()
+            .author(CreateEmbedAuthor::new(match entry.now_playing() {
+                true => format!("{} is listening to", target.name),
+                false => format!("{} last scrobbled", target.name),
+            }))
+            .title(format!("{} - {}", entry.artist, entry.track));
+        if let Some(url) = &entry.url {
+            embed = embed.url(url);
+        }
+        if let Some(album) = &entry.album {
+            embed = embed.field("Album", album, true);
+        }
+        if let Some(timestamp) = entry.timestamp {
+            embed = embed.field("Scrobbled", format!("<t:{}:R>", timestamp), true);
+        }
+        if let Some(track_plays) = track_plays {
+            embed = embed.field("Track plays", track_plays.to_string(), true);
+        }
+        if let Some(artist_plays) = artist_plays {
+            embed = embed.field("Artist plays", artist_plays.to_string(), true);
+        }
+        embed = with_verified_footer(embed, &user);
+
+        let mut reply = CreateReply::default();
+        let art = match &entry.art {
+            Some(art) => fetch_art(client, &user, art).await,
+            None => None,
+        };
+        if let Some(art) = art {
+            reply = reply.attachment(CreateAttachment::bytes(art, "art.png"));
+            embed = embed.thumbnail("attachment://art.png");
+        }
+
+        ctx.send(reply.embed(embed)).await.unwrap();
+    }
+}
+
+/// How many times the user has played the artist and the track of `entry`, where the backend can tell cheaply.
+pub async fn get_track_playcounts(
+    client: Client,
+    ctx: Context<'_>,
+    user: &MljboardUser,
+    entry: &HistoryEntry,
+) -> (Option<u64>, Option<u64>) {
+    match user {
+        MljboardUser::LastFMUser(lfm) => {
+            let lastfm = ctx.data().lastfm_for(lfm);
+            let artist_plays = lastfm
+                .artist_get_user_playcount(entry.artist.clone(), lfm.username.clone())
+                .await
+                .ok();
+            let track_plays = lastfm
+                .track_get_user_playcount(
+                    entry.artist.clone(),
+                    entry.track.clone(),
+                    lfm.username.clone(),
+                )
+                .await
+                .ok();
+            (artist_plays, track_plays)
+        }
+        MljboardUser::MalojaUser(creds) => {
+            let artist_plays = numscrobbles_async(
+                Some(entry.artist.clone()),
+                MljRange::AllTime,
+                creds.clone(),
+                client,
+            )
+            .await
+            .ok();
+            (artist_plays, None)
+        }
+        MljboardUser::ListenBrainzUser(lb) => {
+            let artist_plays = ctx
+                .data()
+                .listenbrainz
+                .artist_listen_count(lb.username.clone(), entry.artist.clone())
+                .await
+                .ok();
+            (artist_plays, None)
+        }
+    }
+}
+
+/// How many scrobbles `/recent` fetches, and how many it shows per page.
+pub const RECENT_SIZE: usize = 50;
+pub const RECENT_PAGE_SIZE: usize = 10;
+
+/// When a scrobble happened, on the clock of someone in `tz`.
+fn format_local_time(timestamp: i64, tz: Option<Tz>) -> String {
+    let Some(utc) = DateTime::<Utc>::from_timestamp(timestamp, 0) else {
+        return "Unknown".to_string();
+    };
+    match tz {
+        Some(tz) => utc.with_timezone(&tz).format("%Y-%m-%d %H:%M").to_string(),
+        None => utc.format("%Y-%m-%d %H:%M UTC").to_string(),
+    }
+}
+
+pub async fn recent_cmd(
+    client: Client,
+    user: Option<MljboardUser>,
+    _msg: Option<Message>,
+    ctx: Context<'_>,
+    tz: Option<Tz>,
+    target: User,
+) {
+    if let Some(user) = user {
+        ctx.defer().await.unwrap();
+
+        let entries = match recent_history(client, ctx, &user, RECENT_SIZE).await {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::error!("Error getting recent history for {}: {}", target.name, err);
+                ctx.reply("There was an error getting your recent tracks.")
+                    .await
+                    .unwrap();
+                return;
+            }
+        };
+        if entries.is_empty() {
+            ctx.reply(format!("{} hasn't scrobbled anything yet.", target.name))
+                .await
+                .unwrap();
+            return;
+        }
+
+        let title = format!("{}'s recent tracks", target.name);
+        let page_count = entries.len().div_ceil(RECENT_PAGE_SIZE);
+        let pages = entries
+            .chunks(RECENT_PAGE_SIZE)
+            .enumerate()
+            .map(|(page, chunk)| {
+                let lines = chunk
+                    .iter()
+                    .map(|entry| match entry.timestamp {
+                        Some(timestamp) => format!(
+                            "**{}** by {} - {}",
+                            entry.track,
+                            entry.artist,
+                            format_local_time(timestamp, tz)
+                        ),
+                        None => format!("▶️ **{}** by {} - Now playing", entry.track, entry.artist),
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                CreateEmbed::new()
+                    .title(title.clone())
+                    .description(lines)
+                    .footer(CreateEmbedFooter::new(format!(
+                        "Page {} of {}",
+                        page + 1,
+                        page_count
+                    )))
+            })
+            .collect();
+        paginate(ctx, pages).await;
+    }
+}
+
+/// How many entries `/top` fetches, and how many it shows per page.
+pub const TOP_CHART_SIZE: usize = 100;
+pub const TOP_PAGE_SIZE: usize = 10;
+
+pub fn format_chart_entry(rank: usize, entry: &ChartEntry) -> String {
+    let name = match &entry.url {
+        Some(url) => format!("[{}]({})", entry.name, url),
+        None => entry.name.clone(),
+    };
+    let plays = match entry.count {
+        1 => "1 scrobble".to_string(),
+        count => format!("{} scrobbles", count),
+    };
+    match &entry.artist {
+        Some(artist) => format!("{}. **{}** by {} - {}", rank, name, artist, plays),
+        None => format!("{}. **{}** - {}", rank, name, plays),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn top_cmd(
+    client: Client,
+    user: Option<MljboardUser>,
+    _msg: Option<Message>,
+    ctx: Context<'_>,
+    kind: ChartKind,
+    range: StatsRange,
+    tz: Option<Tz>,
+    target: User,
+) {
+    if let Some(user) = user {
+        ctx.defer().await.unwrap();
+
+        let entries = match get_chart(client, ctx, &user, kind, &range, tz, TOP_CHART_SIZE).await {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::error!("Error getting {} chart: {}", kind.name(), err);
+                ctx.reply(format!(
+                    "There was an error getting your {} chart.",
+                    kind.name().to_lowercase()
+                ))
+                .await
+                .unwrap();
+                return;
+            }
+        };
+        if entries.is_empty() {
+            ctx.reply("Nothing was scrobbled in that range.")
+                .await
+                .unwrap();
+            return;
+        }
+
+        let title = format!(
+            "{}'s top {} ({})",
+            target.name,
+            kind.name().to_lowercase(),
+            range
+        );
+        let page_count = entries.len().div_ceil(TOP_PAGE_SIZE);
+        let pages = entries
+            .chunks(TOP_PAGE_SIZE)
+            .enumerate()
+            .map(|(page, chunk)| {
+                let lines = chunk
+                    .iter()
+                    .enumerate()
+                    .map(|(i, entry)| format_chart_entry(page * TOP_PAGE_SIZE + i + 1, entry))
+                    .collect::<Vec<String>>()
+                    .join("\n");
+                CreateEmbed::new()
+                    .title(title.clone())
+                    .description(lines)
+                    .footer(CreateEmbedFooter::new(format!(
+                        "Page {} of {}",
+                        page + 1,
+                        page_count
+                    )))
+            })
+            .collect();
+
+        paginate(ctx, pages).await;
+    }
+}
+
+/// How many top artists of each user `/compare` looks at, and how many shared ones it lists.
+pub const COMPARE_CHART_SIZE: usize = 500;
+pub const COMPARE_SHOWN_ARTISTS: usize = 10;
+
+/// How much two charts overlap, from 0 to 1. Each shared artist adds the smaller of the two users' shares of their plays,
+/// so two users with the exact same listening get 1 and a shared artist one of them barely played adds little.
+pub fn compatibility(ours: &[ChartEntry], theirs: &[ChartEntry]) -> (f64, Vec<(String, u64, u64)>) {
+    let our_total: u64 = ours.iter().map(|x| x.count).sum();
+    let their_total: u64 = theirs.iter().map(|x| x.count).sum();
+    if our_total == 0 || their_total == 0 {
+        return (0.0, vec![]);
+    }
+
+    let mut score = 0.0;
+    let mut shared = vec![];
+    for our_entry in ours {
+        if let Some(their_entry) = theirs
+            .iter()
+            .find(|x| artist_key(&x.name) == artist_key(&our_entry.name))
+        {
+            let our_share = our_entry.count as f64 / our_total as f64;
+            let their_share = their_entry.count as f64 / their_total as f64;
+            score += our_share.min(their_share);
+            shared.push((
+                our_share.min(their_share),
+                our_entry.name.clone(),
+                our_entry.count,
+                their_entry.count,
+            ));
+        }
+    }
+    shared.sort_by(|a, b| b.0.total_cmp(&a.0));
+    (
+        score,
+        shared
+            .into_iter()
+            .map(|(_, name, our_count, their_count)| (name, our_count, their_count))
+            .collect(),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn compare_cmd(
+    client: Client,
+    user: Option<MljboardUser>,
+    _msg: Option<Message>,
+    ctx: Context<'_>,
+    other_user: MljboardUser,
+    range: StatsRange,
+    tz: Option<Tz>,
+    target: User,
+) {
+    if let Some(user) = user {
+        ctx.defer().await.unwrap();
+
+        // both charts cover the same stretch of time, counted in the caller's timezone
+        let (ours, theirs) = futures::join!(
+            get_chart(
+                client.clone(),
+                ctx,
+                &user,
+                ChartKind::Artists,
+                &range,
+                tz,
+                COMPARE_CHART_SIZE
+            ),
+            get_chart(
+                client.clone(),
+                ctx,
+                &other_user,
+                ChartKind::Artists,
+                &range,
+                tz,
+                COMPARE_CHART_SIZE
+            ),
+        );
+        let (ours, theirs) = match (ours, theirs) {
+            (Ok(ours), Ok(theirs)) => (ours, theirs),
+            (Err(err), _) | (_, Err(err)) => {
+                log::error!("Error getting artist charts to compare: {}", err);
+                ctx.reply("There was an error getting the artist charts.")
+                    .await
+                    .unwrap();
+                return;
+            }
+        };
+
+        let (score, shared) = compatibility(&ours, &theirs);
+        let mut embed = CreateEmbed::new().title(format!(
+            "{} and {} ({})",
+            ctx.author().name,
+            target.name,
+            range
+        ));
+        if shared.is_empty() {
+            embed = embed.description("No artists in common.");
+        } else {
+            let lines = shared
+                .iter()
+                .take(COMPARE_SHOWN_ARTISTS)
+                .map(|(name, our_count, their_count)| {
+                    format!("**{}** - {} vs {}", name, our_count, their_count)
+                })
+                .collect::<Vec<String>>()
+                .join("\n");
+            embed = embed
+                .description(format!("Compatibility: **{:.0}%**", score * 100.0))
+                .field(format!("Shared artists ({})", shared.len()), lines, false);
+        }
+
+        ctx.send(CreateReply::default().embed(embed)).await.unwrap();
+    }
+}
+
+fn format_days(days: u64) -> String {
+    match days {
+        1 => "1 day".to_string(),
+        days => format!("{} days", days),
+    }
+}
+
+pub async fn streak_cmd(
+    client: Client,
+    user: Option<MljboardUser>,
+    _msg: Option<Message>,
+    ctx: Context<'_>,
+    artist: Option<String>,
+    tz: Option<Tz>,
+    target: User,
+) {
+    if let Some(user) = user {
+        ctx.defer().await.unwrap();
+
+        let entries = match history_in_range(client, ctx, &user, &StatsRange::AllTime, tz).await {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::error!("Error getting history for streaks: {}", err);
+                ctx.reply("There was an error getting your scrobbles.")
+                    .await
+                    .unwrap();
+                return;
             }
-            MljboardUser::LastFMUser(_) => {
-                ctx.reply("Grids are not implemented for Last.FM users yet.")
+        };
+        let entries: Vec<HistoryEntry> = match &artist {
+            Some(artist) => entries
+                .into_iter()
+                .filter(|entry| credits(&entry.artist, artist, user.comma_joined_credits()))
+                .collect(),
+            None => entries,
+        };
+
+        let days = daily_counts(&entries, tz);
+        let streak = streaks(&days, today(tz));
+
+        let title = match &artist {
+            Some(artist) => format!("{}'s {} streak", target.name, artist),
+            None => format!("{}'s listening streak", target.name),
+        };
+        let current = match streak.current_start {
+            Some(start) if streak.current > 0 => {
+                format!("{} (since {})", format_days(streak.current), start)
+            }
+            _ => "No streak going right now".to_string(),
+        };
+        let longest = match (streak.longest_start, streak.longest_end) {
+            (Some(start), Some(end)) => {
+                format!("{} ({} to {})", format_days(streak.longest), start, end)
+            }
+            _ => "Nothing scrobbled yet".to_string(),
+        };
+
+        let embed = CreateEmbed::new()
+            .title(title)
+            .field("Current streak", current, false)
+            .field("Longest streak", longest, false)
+            .field("Days with scrobbles", days.len().to_string(), false);
+        ctx.send(CreateReply::default().embed(embed)).await.unwrap();
+    }
+}
+
+pub async fn calendar_cmd(
+    client: Client,
+    user: Option<MljboardUser>,
+    _msg: Option<Message>,
+    ctx: Context<'_>,
+    month: StatsRange,
+    tz: Option<Tz>,
+    target: User,
+) {
+    if let Some(user) = user {
+        // the calendar is always one whole month
+        let first = match month {
+            StatsRange::Month | StatsRange::CalendarMonth { .. } => {
+                month.days(today(tz)).map(|(first, _)| first)
+            }
+            _ => None,
+        };
+        let Some(first) = first else {
+            ctx.reply("Pick a month, like \"month\", \"March 2024\" or \"2024-03\".")
+                .await
+                .unwrap();
+            return;
+        };
+        let month = StatsRange::CalendarMonth {
+            year: first.year(),
+            month: first.month(),
+        };
+        ctx.defer().await.unwrap();
+
+        let entries = match history_in_range(client, ctx, &user, &month, tz).await {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::error!("Error getting history for calendar: {}", err);
+                ctx.reply("There was an error getting your scrobbles.")
+                    .await
+                    .unwrap();
+                return;
+            }
+        };
+        let days = daily_counts(&entries, tz);
+        let total: u64 = days.values().sum();
+
+        let embed = CreateEmbed::new()
+            .title(format!("{}'s scrobbles in {}", target.name, month))
+            .description(format!(
+                "```\n{}```",
+                render_calendar(first.year(), first.month(), &days)
+            ))
+            .footer(CreateEmbedFooter::new(format!(
+                "{} scrobbles on {} days",
+                total,
+                days.len()
+            )));
+        ctx.send(CreateReply::default().embed(embed)).await.unwrap();
+    }
+}
+
+const WEEKDAY_NAMES: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+pub async fn heatmap_cmd(
+    client: Client,
+    user: Option<MljboardUser>,
+    _msg: Option<Message>,
+    ctx: Context<'_>,
+    range: StatsRange,
+    tz: Option<Tz>,
+    target: User,
+) {
+    if let Some(user) = user {
+        ctx.defer().await.unwrap();
+
+        let entries = match history_in_range(client, ctx, &user, &range, tz).await {
+            Ok(entries) => entries,
+            Err(err) => {
+                log::error!("Error getting history for heatmap: {}", err);
+                ctx.reply("There was an error getting your scrobbles.")
                     .await
                     .unwrap();
+                return;
             }
+        };
+        if entries.is_empty() {
+            ctx.reply("Nothing was scrobbled in that range.")
+                .await
+                .unwrap();
+            return;
         }
+
+        let counts = weekday_hour_counts(&entries, tz);
+        let (busiest_day, busiest_hour, busiest_count) = counts
+            .iter()
+            .enumerate()
+            .flat_map(|(day, hours)| {
+                hours
+                    .iter()
+                    .enumerate()
+                    .map(move |(hour, count)| (day, hour, *count))
+            })
+            .max_by_key(|(_, _, count)| *count)
+            .unwrap_or((0, 0, 0));
+
+        let attachment = CreateAttachment::bytes(render_heatmap(&counts), "heatmap.png");
+        let embed = CreateEmbed::new()
+            .title(format!("When {} listens ({})", target.name, range))
+            .description(format!(
+                "Rows are Monday to Sunday, columns are midnight to 11 PM ({}).\nBusiest: {}s at {:02}:00, {} scrobbles.",
+                tz.map(|x| x.name().to_string()).unwrap_or("UTC".to_string()),
+                WEEKDAY_NAMES[busiest_day],
+                busiest_hour,
+                busiest_count
+            ))
+            .attachment("heatmap.png");
+        ctx.send(CreateReply::default().attachment(attachment).embed(embed))
+            .await
+            .unwrap();
+    }
+}
+
+/// How many entries each `/wrapped` list shows, and how big the album collage is.
+pub const WRAPPED_LIST_SIZE: usize = 10;
+pub const WRAPPED_COLLAGE_SIZE: usize = 3;
+pub const WRAPPED_FIRST_YEAR: i32 = 1970;
+
+fn chart_page(title: String, chart: &[ChartEntry]) -> CreateEmbed {
+    let lines = chart
+        .iter()
+        .take(WRAPPED_LIST_SIZE)
+        .enumerate()
+        .map(|(i, entry)| format_chart_entry(i + 1, entry))
+        .collect::<Vec<String>>()
+        .join("\n");
+    CreateEmbed::new()
+        .title(title)
+        .description(match lines.is_empty() {
+            true => "Nothing here.".to_string(),
+            false => lines,
+        })
+}
+
+/// How long `entries` add up to, for the scrobbles whose backend said how long the track was.
+pub fn listening_minutes(entries: &[HistoryEntry]) -> String {
+    let durations: Vec<u64> = entries.iter().filter_map(|entry| entry.duration).collect();
+    if durations.is_empty() {
+        return "Unavailable, track lengths aren't reported".to_string();
+    }
+    let minutes = durations.iter().sum::<u64>() / 60;
+    match durations.len() == entries.len() {
+        true => format!("{} minutes", minutes),
+        false => format!(
+            "{} minutes, from the {} of {} scrobbles with a length",
+            minutes,
+            durations.len(),
+            entries.len()
+        ),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn wrapped_cmd(
+    client: Client,
+    user: Option<MljboardUser>,
+    _msg: Option<Message>,
+    ctx: Context<'_>,
+    year: i32,
+    tz: Option<Tz>,
+    target: User,
+) {
+    if let Some(user) = user {
+        // nobody scrobbled before 1970 or in the future, and a huge year would have no bounds at all
+        let this_year = today(tz).year();
+        if !(WRAPPED_FIRST_YEAR..=this_year).contains(&year) {
+            ctx.reply(format!(
+                "Pick a year from {} to {}.",
+                WRAPPED_FIRST_YEAR, this_year
+            ))
+            .await
+            .unwrap();
+            return;
+        }
+        ctx.defer().await.unwrap();
+
+        // the whole history, since telling discoveries apart needs everything before the year too
+        let entries =
+            match history_in_range(client.clone(), ctx, &user, &StatsRange::AllTime, tz).await {
+                Ok(entries) => entries,
+                Err(err) => {
+                    log::error!("Error getting history for wrapped: {}", err);
+                    ctx.reply("There was an error getting your scrobbles.")
+                        .await
+                        .unwrap();
+                    return;
+                }
+            };
+        let bounds = StatsRange::CalendarYear(year).bounds(tz);
+        let (start, end) = (bounds.start.unwrap_or(0), bounds.end.unwrap_or(i64::MAX));
+        let (year_entries, earlier_entries): (Vec<HistoryEntry>, Vec<HistoryEntry>) = entries
+            .into_iter()
+            .filter(|entry| entry.timestamp.is_some_and(|x| x <= end))
+            .partition(|entry| entry.timestamp.is_some_and(|x| x >= start));
+        if year_entries.is_empty() {
+            ctx.reply(format!(
+                "{} didn't scrobble anything in {}.",
+                target.name, year
+            ))
+            .await
+            .unwrap();
+            return;
+        }
+
+        let days = daily_counts(&year_entries, tz);
+        let mut months = [0u64; 12];
+        for (date, count) in days.iter() {
+            months[date.month0() as usize] += count;
+        }
+        let busiest_day = days.iter().max_by_key(|(_, count)| **count);
+        let busiest_month = months
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, count)| **count)
+            .map(|(month, count)| (MONTH_NAMES[month], *count));
+
+        let artists = chart_from_history(year_entries.clone(), ChartKind::Artists);
+        let albums = chart_from_history(year_entries.clone(), ChartKind::Albums);
+        let tracks = chart_from_history(year_entries.clone(), ChartKind::Tracks);
+
+        let known_artists: HashSet<String> = earlier_entries
+            .iter()
+            .flat_map(|entry| split_artists(&entry.artist))
+            .map(|artist| normalize(&artist))
+            .collect();
+        let discoveries: Vec<ChartEntry> = artists
+            .iter()
+            .filter(|artist| !known_artists.contains(&normalize(&artist.name)))
+            .cloned()
+            .collect();
+
+        let mut collage = vec![];
+        for album in albums.iter().take(WRAPPED_COLLAGE_SIZE.pow(2)) {
+            if let Some(art) = chart_art(client.clone(), ctx, &user, album).await {
+                collage.push(art);
+            }
+        }
+        let mut attachments = vec![];
+        if !collage.is_empty() {
+            attachments.push(CreateAttachment::bytes(
+                render_grid(collage, WRAPPED_COLLAGE_SIZE),
+                "collage.png",
+            ));
+        }
+
+        let listening_time = listening_minutes(&year_entries);
+
+        let title = format!("{}'s {} wrapped", target.name, year);
+        let mut overview = CreateEmbed::new()
+            .title(title.clone())
+            .field("Scrobbles", year_entries.len().to_string(), true)
+            .field("Artists", artists.len().to_string(), true)
+            .field("New artists", discoveries.len().to_string(), true)
+            .field("Days with scrobbles", days.len().to_string(), true)
+            .field("Listening time", listening_time, true);
+        if let Some((date, count)) = busiest_day {
+            overview = overview.field(
+                "Busiest day",
+                format!("{}, {} scrobbles", date, count),
+                true,
+            );
+        }
+        if let Some((month, count)) = busiest_month {
+            overview = overview.field(
+                "Busiest month",
+                format!("{}, {} scrobbles", month, count),
+                true,
+            );
+        }
+
+        let mut pages = vec![
+            overview,
+            chart_page(format!("{}: top artists", title), &artists),
+            chart_page(format!("{}: top albums", title), &albums),
+            chart_page(format!("{}: top tracks", title), &tracks),
+            chart_page(format!("{}: new discoveries", title), &discoveries),
+        ];
+        if !attachments.is_empty() {
+            // the collage is the picture on the first page and a thumbnail on the rest, so it's never a loose file
+            pages = pages
+                .into_iter()
+                .enumerate()
+                .map(|(i, page)| match i {
+                    0 => page.image("attachment://collage.png"),
+                    _ => page.thumbnail("attachment://collage.png"),
+                })
+                .collect();
+        }
+        let page_count = pages.len();
+        let pages = pages
+            .into_iter()
+            .enumerate()
+            .map(|(i, page)| {
+                page.footer(CreateEmbedFooter::new(format!(
+                    "Page {} of {}",
+                    i + 1,
+                    page_count
+                )))
+            })
+            .collect();
+
+        paginate_with_attachments(ctx, pages, attachments).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chart(artists: &[(&str, u64)]) -> Vec<ChartEntry> {
+        artists
+            .iter()
+            .map(|(name, count)| ChartEntry {
+                name: name.to_string(),
+                artist: None,
+                count: *count,
+                url: None,
+                art: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_listening_is_fully_compatible() {
+        let ours = chart(&[("Radiohead", 30), ("Björk", 10)]);
+        let (score, shared) = compatibility(&ours, &ours);
+        assert!((score - 1.0).abs() < 1e-9);
+        assert_eq!(shared.len(), 2);
+    }
+
+    #[test]
+    fn nothing_shared_is_not_compatible() {
+        let (score, shared) = compatibility(&chart(&[("Radiohead", 30)]), &chart(&[("Björk", 10)]));
+        assert_eq!(score, 0.0);
+        assert!(shared.is_empty());
+        assert_eq!(compatibility(&[], &chart(&[("Björk", 10)])).0, 0.0);
+    }
+
+    #[test]
+    fn shared_artists_count_the_smaller_share() {
+        let ours = chart(&[("The Beatles", 50), ("Radiohead", 50)]);
+        let theirs = chart(&[("beatles", 10), ("Björk", 90)]);
+        let (score, shared) = compatibility(&ours, &theirs);
+        assert!((score - 0.1).abs() < 1e-9);
+        assert_eq!(shared, vec![("The Beatles".to_string(), 50, 10)]);
+    }
+
+    #[test]
+    fn biggest_overlap_comes_first() {
+        let ours = chart(&[("A", 10), ("B", 10), ("C", 80)]);
+        let theirs = chart(&[("A", 50), ("C", 50)]);
+        let (_, shared) = compatibility(&ours, &theirs);
+        let names: Vec<&str> = shared.iter().map(|(name, _, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["C", "A"]);
+    }
+
+    #[test]
+    fn listening_time_counts_only_known_lengths() {
+        let entry = |duration| HistoryEntry {
+            timestamp: Some(0),
+            artist: "Björk".to_string(),
+            track: "Joga".to_string(),
+            album: None,
+            url: None,
+            art: None,
+            duration,
+        };
+        assert_eq!(
+            listening_minutes(&[entry(Some(300)), entry(Some(330))]),
+            "10 minutes"
+        );
+        assert_eq!(
+            listening_minutes(&[entry(Some(600)), entry(None)]),
+            "10 minutes, from the 1 of 2 scrobbles with a length"
+        );
+        assert!(listening_minutes(&[entry(None)]).starts_with("Unavailable"));
     }
 }
@@ -0,0 +1,85 @@
+use super::bot::Context;
+use poise::{serenity_prelude::*, CreateReply};
+use std::time::Duration;
+
+/// How long the buttons keep working after the last press.
+const PAGINATION_TIMEOUT: Duration = Duration::from_secs(120);
+
+fn page_buttons(page: usize, page_count: usize) -> Vec<CreateActionRow> {
+    vec![CreateActionRow::Buttons(vec![
+        CreateButton::new("prev")
+            .label("Previous")
+            .disabled(page == 0),
+        CreateButton::new("next")
+            .label("Next")
+            .disabled(page + 1 >= page_count),
+    ])]
+}
+
+/// Sends the first page with previous/next buttons, and flips through `pages` as the caller presses them.
+pub async fn paginate(ctx: Context<'_>, pages: Vec<CreateEmbed>) {
+    paginate_with_attachments(ctx, pages, vec![]).await;
+}
+
+/// Like `paginate`, with files sent along with the message. They stay through page changes,
+/// so pages refer to them with `attachment://` URLs.
+pub async fn paginate_with_attachments(
+    ctx: Context<'_>,
+    pages: Vec<CreateEmbed>,
+    attachments: Vec<CreateAttachment>,
+) {
+    let Some(first) = pages.first() else {
+        return;
+    };
+    let mut reply = CreateReply::default().embed(first.clone());
+    for attachment in attachments {
+        reply = reply.attachment(attachment);
+    }
+    if pages.len() == 1 {
+        ctx.send(reply).await.unwrap();
+        return;
+    }
+
+    let mut page = 0;
+    let message = ctx
+        .send(reply.components(page_buttons(page, pages.len())))
+        .await
+        .unwrap();
+
+    while let Some(press) = message
+        .message()
+        .await
+        .unwrap()
+        .await_component_interaction(ctx)
+        .author_id(ctx.author().id)
+        .timeout(PAGINATION_TIMEOUT)
+        .await
+    {
+        match press.data.custom_id.as_str() {
+            "prev" => page = page.saturating_sub(1),
+            "next" => page = (page + 1).min(pages.len() - 1),
+            _ => continue,
+        }
+        press
+            .create_response(
+                ctx,
+                CreateInteractionResponse::UpdateMessage(
+                    CreateInteractionResponseMessage::new()
+                        .embed(pages[page].clone())
+                        .components(page_buttons(page, pages.len())),
+                ),
+            )
+            .await
+            .unwrap();
+    }
+
+    // nobody's paging anymore, take the dead buttons away
+    let _ = message
+        .edit(
+            ctx,
+            CreateReply::default()
+                .embed(pages[page].clone())
+                .components(vec![]),
+        )
+        .await;
+}
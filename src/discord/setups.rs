@@ -1,8 +1,36 @@
 use super::bot::Context;
 use crate::db::postgres::*;
+use crate::discord::lastfm::LastFMService;
 use crate::dm_channel;
-use serenity::all::CreateMessage;
+use crate::lfm::{LastFMApi, LASTFM_ERROR_UNAUTHORIZED_TOKEN, LIBREFM_API_BASE};
+use crate::listenbrainz::{is_valid_username, ListenBrainzError};
+use chrono_tz::Tz;
+use poise::CreateReply;
+use serenity::all::{CreateEmbed, CreateMessage};
 use sqlx::PgPool;
+use std::time::Duration;
+
+/// How often `lfm_verify` asks Last.FM whether the token has been approved, and for how long.
+const LASTFM_AUTH_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const LASTFM_AUTH_POLL_ATTEMPTS: u32 = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, poise::ChoiceParameter)]
+pub enum Visibility {
+    #[name = "Public, anyone can look up my stats"]
+    Public,
+    #[name = "Private, only I can see my stats"]
+    Private,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, poise::ChoiceParameter)]
+pub enum MilestoneAnnouncements {
+    #[name = "In my DMs"]
+    Dm,
+    #[name = "In this channel"]
+    Here,
+    #[name = "Off"]
+    Off,
+}
 
 pub async fn hos_setup(ctx: Context<'_>, pool: &PgPool, formatted_user: String) {
     if let Some(dm_channel) = dm_channel!(ctx) {
@@ -68,7 +96,14 @@ pub async fn website_setup(ctx: Context<'_>, pool: &PgPool, formatted_user: Stri
     }
 }
 
-pub async fn lfm_setup(ctx: Context<'_>, pool: &PgPool, formatted_user: String, arg: String) {
+pub async fn lfm_setup(
+    ctx: Context<'_>,
+    pool: &PgPool,
+    formatted_user: String,
+    arg: String,
+    service: LastFMService,
+    api_url: Option<String>,
+) {
     let mut match_found = false;
 
     let query: Vec<DiscordLastFMUser> = get_lastfm_username(pool, formatted_user.clone()).await;
@@ -90,22 +125,350 @@ pub async fn lfm_setup(ctx: Context<'_>, pool: &PgPool, formatted_user: String,
                 .unwrap(); // as far as I know, the limit is 15 characters
             return;
         }
-        if !arg.chars().all(char::is_alphanumeric) {
+        if !arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
             ctx.say("Your provided Last.FM username is invalid.")
                 .await
                 .unwrap();
             return;
         }
-        ctx.say(format!("Setting your Last.FM username to {}.", arg))
+
+        let Some(lastfm) = ctx.data().lastfm.clone() else {
+            ctx.say("The bot owner has not set up a Last.FM API key.")
+                .await
+                .unwrap();
+            return;
+        };
+
+        let service_url = match service {
+            LastFMService::LastFM => None,
+            LastFMService::LibreFM => Some(LIBREFM_API_BASE.to_string()),
+            LastFMService::GnuFM => match api_url {
+                Some(api_url) => {
+                    if let Err(reason) = LastFMApi::check_service_url(&api_url).await {
+                        ctx.say(reason).await.unwrap();
+                        return;
+                    }
+                    Some(api_url)
+                }
+                None => {
+                    ctx.say("Give the API URL of your GNU FM server, starting with `http://` or `https://`.")
+                        .await
+                        .unwrap();
+                    return;
+                }
+            },
+        };
+        let lastfm = lastfm.for_service(service_url.clone());
+
+        // Last.FM accounts are checked through lfm-stats, other services only speak the API
+        let scrobbles = match &service_url {
+            None => {
+                lastfm.limiter.acquire().await;
+                match lfm_stats::user_get_info(arg.clone(), lastfm.api_key.clone()).await {
+                    Ok(info) => Some(info.scrobbles),
+                    Err(_) => {
+                        ctx.say("That Last.FM user doesn't exist.").await.unwrap();
+                        return;
+                    }
+                }
+            }
+            Some(_) => None,
+        };
+
+        // the account's own casing and registration date come from user.getInfo
+        let info = match lastfm.user_get_info(arg).await {
+            Ok(info) => info,
+            Err(err) if err.not_found() => {
+                ctx.say(format!(
+                    "That {} user doesn't exist.",
+                    lastfm.service_name()
+                ))
+                .await
+                .unwrap();
+                return;
+            }
+            Err(err) => {
+                log::error!(
+                    "Error looking up {} user for {}: {}",
+                    lastfm.service_name(),
+                    formatted_user,
+                    err
+                );
+                ctx.say(format!(
+                    "Couldn't reach {} to check that username, try again later.",
+                    lastfm.service_name()
+                ))
+                .await
+                .unwrap();
+                return;
+            }
+        };
+
+        // Last.FM matches names case-insensitively, store the casing the account actually uses
+        let username = info.name;
+        ctx.send(
+            CreateReply::default().embed(
+                CreateEmbed::new()
+                    .title(format!(
+                        "Setting your {} username to {}.",
+                        lastfm.service_name(),
+                        username
+                    ))
+                    .field(
+                        "Scrobbles",
+                        scrobbles.map(|x| x.to_string()).unwrap_or(info.playcount),
+                        true,
+                    )
+                    .field(
+                        "Registered",
+                        format!("<t:{}:D>", info.registered.unixtime),
+                        true,
+                    ),
+            ),
+        )
+        .await
+        .unwrap();
+
+        insert_lastfm_user(pool, formatted_user, username, service_url).await;
+    } else {
+        ctx.say("No username provided.").await.unwrap();
+    }
+}
+
+pub async fn lb_setup(ctx: Context<'_>, pool: &PgPool, formatted_user: String, arg: String) {
+    let query = get_listenbrainz_username(pool, formatted_user.clone()).await;
+    if !query.is_empty() {
+        ctx.say("You've already set a ListenBrainz username. Do `!reset` to remove it.")
+            .await
+            .unwrap();
+        return;
+    }
+
+    if arg.is_empty() {
+        ctx.say("No username provided.").await.unwrap();
+        return;
+    }
+    if arg.len() >= 64 || !is_valid_username(&arg) {
+        ctx.say("Your provided ListenBrainz username is invalid.")
+            .await
+            .unwrap();
+        return;
+    }
+
+    match ctx.data().listenbrainz.listen_count(arg.clone()).await {
+        Ok(count) => {
+            ctx.say(format!(
+                "Setting your ListenBrainz username to {} ({} listens).",
+                arg, count
+            ))
+            .await
+            .unwrap();
+            insert_listenbrainz_user(pool, formatted_user, arg).await;
+        }
+        Err(ListenBrainzError::UserNotFound) => {
+            ctx.say("That ListenBrainz user doesn't exist.")
+                .await
+                .unwrap();
+        }
+        Err(err) => {
+            log::error!(
+                "Error looking up ListenBrainz user for {}: {}",
+                formatted_user,
+                err
+            );
+            ctx.say("Couldn't reach ListenBrainz to check that username, try again later.")
+                .await
+                .unwrap();
+        }
+    }
+}
+
+pub async fn lfm_verify(ctx: Context<'_>, pool: &PgPool, formatted_user: String) {
+    let mut linked_username: Option<String> = None;
+    let mut service: Option<String> = None;
+    for result in get_lastfm_username(pool, formatted_user.clone()).await {
+        linked_username = result.lastfm_username;
+        service = result.service;
+    }
+
+    let Some(linked_username) = linked_username else {
+        ctx.say("You don't have a Last.FM username set up. Do `/lfm_setup` first.")
             .await
             .unwrap();
+        return;
+    };
 
-        insert_lastfm_user(pool, formatted_user, arg).await;
+    if service.is_some() {
+        // the API secret belongs to Last.FM, other services can't check signatures made with it
+        ctx.say("Only Last.FM accounts can be verified.")
+            .await
+            .unwrap();
+        return;
+    }
+
+    let lastfm = match ctx.data().lastfm.clone() {
+        Some(lastfm) if lastfm.api_secret.is_some() => lastfm,
+        _ => {
+            ctx.say("The bot owner has not set up Last.FM authentication.")
+                .await
+                .unwrap();
+            return;
+        }
+    };
+
+    let token = match lastfm.auth_get_token().await {
+        Ok(token) => token,
+        Err(err) => {
+            log::error!(
+                "Error getting a Last.FM token for {}: {}",
+                formatted_user,
+                err
+            );
+            ctx.say("Couldn't reach Last.FM, try again later.")
+                .await
+                .unwrap();
+            return;
+        }
+    };
+
+    if let Some(dm_channel) = dm_channel!(ctx) {
+        dm_channel
+            .send_message(
+                ctx,
+                CreateMessage::new().content(format!(
+                    "Open {} while logged in as `{}` and allow access. \
+                    mljboard only uses this to confirm the account is yours.",
+                    lastfm.auth_url_for(&token),
+                    linked_username
+                )),
+            )
+            .await
+            .unwrap();
+        let _ = ctx.say("DMed you.").await;
+
+        let mut session = None;
+        for _ in 0..LASTFM_AUTH_POLL_ATTEMPTS {
+            tokio::time::sleep(LASTFM_AUTH_POLL_INTERVAL).await;
+            match lastfm.auth_get_session(token.clone()).await {
+                Ok(ok) => {
+                    session = Some(ok);
+                    break;
+                }
+                Err(err) if err.code() == Some(LASTFM_ERROR_UNAUTHORIZED_TOKEN) => continue,
+                Err(err) => {
+                    log::error!(
+                        "Error getting a Last.FM session for {}: {}",
+                        formatted_user,
+                        err
+                    );
+                    break;
+                }
+            }
+        }
+
+        let content = match session {
+            Some(session) if session.name.to_lowercase() == linked_username.to_lowercase() => {
+                set_lastfm_session_key(pool, formatted_user, session.key).await;
+                format!(
+                    "Verified that you own the Last.FM account `{}`.",
+                    linked_username
+                )
+            }
+            Some(session) => format!(
+                "You allowed access as `{}`, but your linked Last.FM username is `{}`. \
+                Log in as `{}` and run `/lfm_verify` again.",
+                session.name, linked_username, linked_username
+            ),
+            None => "Verification timed out or failed. Run `/lfm_verify` to try again.".to_string(),
+        };
+        dm_channel
+            .send_message(ctx, CreateMessage::new().content(content))
+            .await
+            .unwrap();
     } else {
-        ctx.say("No website provided.").await.unwrap();
+        let _ = ctx.say("Unable to create a DM channel with you.").await;
     }
 }
 
+pub async fn privacy(
+    ctx: Context<'_>,
+    pool: &PgPool,
+    formatted_user: String,
+    visibility: Visibility,
+) {
+    let public = visibility == Visibility::Public;
+    set_privacy_setting(pool, formatted_user, public).await;
+    let content = match public {
+        true => "Other members can now look up your stats.",
+        false => "Only you can see your stats now.",
+    };
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await
+        .unwrap();
+}
+
+pub async fn timezone(
+    ctx: Context<'_>,
+    pool: &PgPool,
+    formatted_user: String,
+    timezone: Option<String>,
+) {
+    let content = match timezone {
+        None => match ctx.data().timezone(formatted_user).await {
+            Some(timezone) => format!("Your timezone is {}.", timezone.name()),
+            None => "You haven't set a timezone, your stats are counted in UTC.".to_string(),
+        },
+        Some(timezone) => match timezone.trim().parse::<Tz>() {
+            Ok(timezone) => {
+                set_user_timezone(pool, formatted_user, timezone.name().to_string()).await;
+                format!("Your stats will be counted in {} now.", timezone.name())
+            }
+            Err(_) => format!(
+                "I don't know the timezone `{}`. Use a name from the tz database, like America/Los_Angeles.",
+                timezone
+            ),
+        },
+    };
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await
+        .unwrap();
+}
+
+pub async fn milestones(
+    ctx: Context<'_>,
+    pool: &PgPool,
+    formatted_user: String,
+    announcements: MilestoneAnnouncements,
+) {
+    let (announce, channel_id, content) = match announcements {
+        MilestoneAnnouncements::Dm => (
+            "dm",
+            None,
+            "I'll DM you when you pass a round number of scrobbles.",
+        ),
+        MilestoneAnnouncements::Here => (
+            "channel",
+            Some(ctx.channel_id().get() as i64),
+            "I'll announce it in this channel when you pass a round number of scrobbles.",
+        ),
+        MilestoneAnnouncements::Off => ("off", None, "No more milestone announcements."),
+    };
+    set_milestone_setting(
+        pool,
+        formatted_user,
+        ctx.author().id.get() as i64,
+        announce.to_string(),
+        channel_id,
+    )
+    .await;
+    ctx.send(CreateReply::default().content(content).ephemeral(true))
+        .await
+        .unwrap();
+}
+
 pub async fn reset(ctx: Context<'_>, pool: &PgPool, formatted_user: String) {
     if let Some(dm_channel) = dm_channel!(ctx) {
         for row in get_websites(pool, formatted_user.clone()).await {
@@ -206,6 +569,31 @@ pub async fn reset(ctx: Context<'_>, pool: &PgPool, formatted_user: String) {
                 .unwrap();
         }
 
+        for row in get_listenbrainz_username(pool, formatted_user.clone()).await {
+            dm_channel
+                .send_message(
+                    ctx,
+                    CreateMessage::new().content(format!(
+                        "Removing your ListenBrainz username `{}` from mljboard's database.",
+                        row.listenbrainz_username.unwrap_or("[none]".to_string())
+                    )),
+                )
+                .await
+                .unwrap();
+        }
+
+        let query = delete_listenbrainz_user(pool, formatted_user.clone()).await;
+
+        if query >= 1 {
+            dm_channel
+                .send_message(
+                    ctx,
+                    CreateMessage::new().content(format!("Removed {} entries.", query)),
+                )
+                .await
+                .unwrap();
+        }
+
         let _ = ctx.say("DMed you.").await;
     } else {
         let _ = ctx.say("Unable to create a DM channel with you.").await;
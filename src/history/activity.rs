@@ -0,0 +1,23 @@
+use super::HistoryEntry;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use chrono_tz::Tz;
+
+/// Scrobbles by weekday and hour of day, `counts[weekday][hour]` with Monday as weekday 0.
+pub fn weekday_hour_counts(entries: &[HistoryEntry], tz: Option<Tz>) -> [[u64; 24]; 7] {
+    let mut counts = [[0; 24]; 7];
+    for utc in entries
+        .iter()
+        .filter_map(|entry| entry.timestamp)
+        .filter_map(|timestamp| DateTime::<Utc>::from_timestamp(timestamp, 0))
+    {
+        let (weekday, hour) = match tz {
+            Some(tz) => {
+                let local = utc.with_timezone(&tz);
+                (local.weekday(), local.hour())
+            }
+            None => (utc.weekday(), utc.hour()),
+        };
+        counts[weekday.num_days_from_monday() as usize][hour as usize] += 1;
+    }
+    counts
+}
@@ -0,0 +1,278 @@
+pub mod activity;
+pub mod streaks;
+
+use crate::discord::bot::{Context, MljboardUser};
+use crate::discord::lastfm::get_lastfm_user;
+use crate::lfm::json::largest_image;
+use crate::lfm::LastFMError;
+use crate::listenbrainz::json::TrackMetadata;
+use crate::listenbrainz::{release_art_url, ListenBrainzError};
+use crate::range::StatsRange;
+use chrono_tz::Tz;
+use mljcl::credentials::MalojaCredentials;
+use mljcl::range::Range as MljRange;
+use reqwest::Client;
+use std::fmt;
+
+/// One scrobble (or listen), the same shape whichever backend it came from.
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    /// `None` for a track that's playing right now and hasn't been scrobbled yet.
+    pub timestamp: Option<i64>,
+    pub artist: String,
+    pub track: String,
+    pub album: Option<String>,
+    pub url: Option<String>,
+    pub art: Option<ArtSource>,
+    /// How long the track is in seconds, `None` when the backend doesn't say.
+    pub duration: Option<u64>,
+}
+
+impl HistoryEntry {
+    pub fn now_playing(&self) -> bool {
+        self.timestamp.is_none()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ArtSource {
+    Url(String),
+    /// Maloja serves art by album ID through its own API.
+    MalojaAlbum(String),
+}
+
+/// A failure from whichever backend a user's stats come from.
+#[derive(Debug)]
+pub enum BackendError {
+    Maloja,
+    LastFM(LastFMError),
+    /// The Last.FM user wasn't found, or they cancelled loading their scrobbles.
+    LastFMSync,
+    ListenBrainz(ListenBrainzError),
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendError::Maloja => write!(f, "Maloja error"),
+            BackendError::LastFM(err) => write!(f, "{}", err),
+            BackendError::LastFMSync => write!(f, "Couldn't load Last.FM scrobbles"),
+            BackendError::ListenBrainz(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+/// Every Maloja scrobble in `range`, newest first.
+pub async fn maloja_history(
+    client: Client,
+    creds: MalojaCredentials,
+    range: MljRange,
+) -> Result<Vec<HistoryEntry>, BackendError> {
+    mljcl::history::scrobbles_async(None, range, creds, client)
+        .await
+        .map(|scrobbles| {
+            scrobbles
+                .into_iter()
+                .map(|scrobble| HistoryEntry {
+                    timestamp: Some(scrobble.time as i64),
+                    artist: scrobble
+                        .track
+                        .artists
+                        .iter()
+                        .map(|artist| artist.name.clone())
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                    track: scrobble.track.title.clone(),
+                    album: scrobble
+                        .track
+                        .album
+                        .as_ref()
+                        .map(|album| album.name.clone()),
+                    url: None,
+                    art: scrobble
+                        .track
+                        .album
+                        .as_ref()
+                        .map(|album| ArtSource::MalojaAlbum(album.id.clone())),
+                    duration: None,
+                })
+                .collect()
+        })
+        .map_err(|_| BackendError::Maloja)
+}
+
+fn listenbrainz_entry(timestamp: Option<i64>, metadata: TrackMetadata) -> HistoryEntry {
+    let duration = metadata
+        .additional_info
+        .as_ref()
+        .and_then(|x| x.duration_ms.map(|ms| ms / 1000).or(x.duration));
+    HistoryEntry {
+        timestamp,
+        artist: metadata.artist_name,
+        track: metadata.track_name,
+        album: metadata.release_name,
+        url: None,
+        art: metadata
+            .mbid_mapping
+            .and_then(|x| x.release_mbid)
+            .map(|mbid| ArtSource::Url(release_art_url(&mbid))),
+        duration,
+    }
+}
+
+/// The latest `limit` entries for `user`, newest first, led by the track playing right now if the backend knows it.
+pub async fn recent_history(
+    client: Client,
+    ctx: Context<'_>,
+    user: &MljboardUser,
+    limit: usize,
+) -> Result<Vec<HistoryEntry>, BackendError> {
+    match user {
+        MljboardUser::MalojaUser(creds) => {
+            // Maloja has no paging in its scrobble list, so widen the range until it holds `limit` scrobbles.
+            // each range contains the ones before it, so the newest are always in the widest one fetched
+            for range in ["today", "thismonth", "thisyear"] {
+                let mut entries = maloja_history(
+                    client.clone(),
+                    creds.clone(),
+                    MljRange::In(range.to_string()),
+                )
+                .await?;
+                if entries.len() >= limit {
+                    entries.truncate(limit);
+                    return Ok(entries);
+                }
+            }
+            let mut entries = maloja_history(client, creds.clone(), MljRange::AllTime).await?;
+            entries.truncate(limit);
+            Ok(entries)
+        }
+        MljboardUser::LastFMUser(lfm) => ctx
+            .data()
+            .lastfm_for(lfm)
+            .user_get_recent_tracks(lfm.username.clone(), None, None, 1, limit)
+            .await
+            .map(|recent| {
+                recent
+                    .track
+                    .into_iter()
+                    .map(|track| HistoryEntry {
+                        timestamp: match track.now_playing() {
+                            true => None,
+                            false => track.timestamp(),
+                        },
+                        art: largest_image(&track.image).map(ArtSource::Url),
+                        artist: track.artist.text,
+                        track: track.name,
+                        album: Some(track.album.text).filter(|x| !x.is_empty()),
+                        url: Some(track.url).filter(|x| !x.is_empty()),
+                        duration: None,
+                    })
+                    .take(limit)
+                    .collect()
+            })
+            .map_err(BackendError::LastFM),
+        MljboardUser::ListenBrainzUser(lb) => {
+            let listenbrainz = &ctx.data().listenbrainz;
+            let mut entries = vec![];
+            if let Some(playing_now) = listenbrainz
+                .playing_now(lb.username.clone())
+                .await
+                .map_err(BackendError::ListenBrainz)?
+            {
+                entries.push(listenbrainz_entry(None, playing_now.track_metadata));
+            }
+            entries.extend(
+                listenbrainz
+                    .latest_listens(lb.username.clone(), limit)
+                    .await
+                    .map_err(BackendError::ListenBrainz)?
+                    .into_iter()
+                    .map(|listen| {
+                        listenbrainz_entry(Some(listen.listened_at), listen.track_metadata)
+                    }),
+            );
+            entries.truncate(limit);
+            Ok(entries)
+        }
+    }
+}
+
+/// Every entry for `user` in `range`, newest first.
+pub async fn history_in_range(
+    client: Client,
+    ctx: Context<'_>,
+    user: &MljboardUser,
+    range: &StatsRange,
+    tz: Option<Tz>,
+) -> Result<Vec<HistoryEntry>, BackendError> {
+    let bounds = range.bounds(tz);
+    match user {
+        MljboardUser::MalojaUser(creds) => {
+            let entries = maloja_history(client, creds.clone(), range.maloja_covering(tz)).await?;
+            Ok(entries
+                .into_iter()
+                .filter(|entry| {
+                    entry.timestamp.is_some_and(|timestamp| {
+                        bounds.start.map_or(true, |start| timestamp >= start)
+                            && bounds.end.map_or(true, |end| timestamp <= end)
+                    })
+                })
+                .collect())
+        }
+        MljboardUser::LastFMUser(lfm) => {
+            let scrobbles = get_lastfm_user(
+                ctx,
+                ctx.data().lastfm_for(lfm),
+                lfm.username.clone(),
+                bounds,
+            )
+            .await
+            .ok_or(BackendError::LastFMSync)?;
+            Ok(scrobbles
+                .into_iter()
+                .map(|scrobble| HistoryEntry {
+                    timestamp: scrobble.scrobbled_at,
+                    artist: scrobble.artist.unwrap_or_default(),
+                    track: scrobble.track.unwrap_or_default(),
+                    album: scrobble.album.filter(|x| !x.is_empty()),
+                    url: scrobble.url.filter(|x| !x.is_empty()),
+                    art: None,
+                    duration: None,
+                })
+                .collect())
+        }
+        MljboardUser::ListenBrainzUser(lb) => ctx
+            .data()
+            .listenbrainz
+            .listens(lb.username.clone(), bounds.start, bounds.end)
+            .await
+            .map(|listens| {
+                listens
+                    .into_iter()
+                    .map(|listen| {
+                        listenbrainz_entry(Some(listen.listened_at), listen.track_metadata)
+                    })
+                    .collect()
+            })
+            .map_err(BackendError::ListenBrainz),
+    }
+}
+
+/// Downloads the art for an entry, Maloja art comes from the user's own server.
+pub async fn fetch_art(client: Client, user: &MljboardUser, art: &ArtSource) -> Option<Vec<u8>> {
+    match (art, user) {
+        (ArtSource::Url(url), _) => {
+            let response = client.get(url).send().await.ok()?;
+            response.bytes().await.ok().map(|x| x.to_vec())
+        }
+        (ArtSource::MalojaAlbum(album_id), MljboardUser::MalojaUser(creds)) => {
+            mljcl::art::album_art_async(album_id.clone(), creds.clone(), client)
+                .await
+                .ok()
+        }
+        (ArtSource::MalojaAlbum(_), _) => None,
+    }
+}
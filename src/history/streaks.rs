@@ -0,0 +1,190 @@
+use super::HistoryEntry;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, Default)]
+pub struct Streaks {
+    /// Days in a row up to today, or up to yesterday if nothing's been played yet today.
+    pub current: u64,
+    pub current_start: Option<NaiveDate>,
+    pub longest: u64,
+    pub longest_start: Option<NaiveDate>,
+    pub longest_end: Option<NaiveDate>,
+}
+
+/// The date a scrobble happened on for someone in `tz`.
+pub fn local_date(timestamp: i64, tz: Option<Tz>) -> Option<NaiveDate> {
+    let utc = DateTime::<Utc>::from_timestamp(timestamp, 0)?;
+    Some(match tz {
+        Some(tz) => utc.with_timezone(&tz).date_naive(),
+        None => utc.date_naive(),
+    })
+}
+
+/// How many scrobbles there are on each day, leaving out the track that's playing right now.
+pub fn daily_counts(entries: &[HistoryEntry], tz: Option<Tz>) -> BTreeMap<NaiveDate, u64> {
+    let mut days = BTreeMap::new();
+    for date in entries
+        .iter()
+        .filter_map(|entry| entry.timestamp)
+        .filter_map(|timestamp| local_date(timestamp, tz))
+    {
+        *days.entry(date).or_insert(0) += 1;
+    }
+    days
+}
+
+pub fn streaks(days: &BTreeMap<NaiveDate, u64>, today: NaiveDate) -> Streaks {
+    let mut ret = Streaks::default();
+
+    let mut run = 0;
+    let mut run_start = None;
+    let mut previous: Option<NaiveDate> = None;
+    for date in days.keys() {
+        match previous {
+            Some(previous) if previous.succ_opt() == Some(*date) => run += 1,
+            _ => {
+                run = 1;
+                run_start = Some(*date);
+            }
+        }
+        if run > ret.longest {
+            ret.longest = run;
+            ret.longest_start = run_start;
+            ret.longest_end = Some(*date);
+        }
+        previous = Some(*date);
+    }
+
+    // a streak is still alive until a whole day passes without anything played
+    if let Some(last) = previous {
+        if last == today || last.succ_opt() == Some(today) {
+            ret.current = run;
+            ret.current_start = run_start;
+        }
+    }
+    ret
+}
+
+/// A month laid out Monday to Sunday, with the scrobble count on each day and a dot on days without any.
+pub fn render_calendar(year: i32, month: u32, days: &BTreeMap<NaiveDate, u64>) -> String {
+    let Some(first) = NaiveDate::from_ymd_opt(year, month, 1) else {
+        return String::new();
+    };
+    let mut ret = String::from("  Mo   Tu   We   Th   Fr   Sa   Su\n");
+    let mut cells = vec![String::new(); first.weekday().num_days_from_monday() as usize];
+    let mut date = first;
+    while date.month() == month {
+        cells.push(match days.get(&date) {
+            Some(count) if *count > 999 => "999+".to_string(),
+            Some(count) => count.to_string(),
+            None => "·".to_string(),
+        });
+        date += Duration::days(1);
+    }
+    for week in cells.chunks(7) {
+        let line = week
+            .iter()
+            .map(|cell| format!("{:>4}", cell))
+            .collect::<Vec<String>>()
+            .join(" ");
+        ret.push_str(line.trim_end());
+        ret.push('\n');
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn days(dates: &[(NaiveDate, u64)]) -> BTreeMap<NaiveDate, u64> {
+        dates.iter().cloned().collect()
+    }
+
+    #[test]
+    fn longest_streak_is_found() {
+        let played = days(&[
+            (date(2024, 1, 1), 1),
+            (date(2024, 1, 2), 4),
+            (date(2024, 1, 3), 2),
+            (date(2024, 1, 5), 1),
+            (date(2024, 1, 6), 1),
+        ]);
+        let streaks = streaks(&played, date(2024, 2, 1));
+        assert_eq!(streaks.longest, 3);
+        assert_eq!(streaks.longest_start, Some(date(2024, 1, 1)));
+        assert_eq!(streaks.longest_end, Some(date(2024, 1, 3)));
+        assert_eq!(streaks.current, 0);
+        assert_eq!(streaks.current_start, None);
+    }
+
+    #[test]
+    fn current_streak_survives_until_a_day_is_missed() {
+        let played = days(&[
+            (date(2023, 12, 30), 1),
+            (date(2023, 12, 31), 1),
+            (date(2024, 1, 1), 1),
+        ]);
+        for today in [date(2024, 1, 1), date(2024, 1, 2)] {
+            let streaks = streaks(&played, today);
+            assert_eq!(streaks.current, 3);
+            assert_eq!(streaks.current_start, Some(date(2023, 12, 30)));
+        }
+        assert_eq!(streaks(&played, date(2024, 1, 3)).current, 0);
+    }
+
+    #[test]
+    fn no_scrobbles_no_streaks() {
+        let streaks = streaks(&BTreeMap::new(), date(2024, 1, 1));
+        assert_eq!(streaks.current, 0);
+        assert_eq!(streaks.longest, 0);
+        assert_eq!(streaks.longest_start, None);
+    }
+
+    #[test]
+    fn daily_counts_skip_now_playing_and_use_the_timezone() {
+        let entry = |timestamp| HistoryEntry {
+            timestamp,
+            artist: "Björk".to_string(),
+            track: "Joga".to_string(),
+            album: None,
+            url: None,
+            art: None,
+            duration: None,
+        };
+        // 2024-01-01 23:30 UTC, which is already the 2nd in Berlin
+        let entries = vec![
+            entry(Some(1704151800)),
+            entry(Some(1704151800)),
+            entry(None),
+        ];
+        assert_eq!(daily_counts(&entries, None), days(&[(date(2024, 1, 1), 2)]));
+        assert_eq!(
+            daily_counts(&entries, Some(chrono_tz::Europe::Berlin)),
+            days(&[(date(2024, 1, 2), 2)])
+        );
+    }
+
+    #[test]
+    fn calendar_starts_on_the_right_weekday() {
+        // February 2024 starts on a Thursday and has 29 days
+        let played = days(&[(date(2024, 2, 2), 3), (date(2024, 2, 29), 1000)]);
+        let calendar = render_calendar(2024, 2, &played);
+        let lines: Vec<&str> = calendar.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], "  Mo   Tu   We   Th   Fr   Sa   Su");
+        assert_eq!(lines[1], "                  ·    3    ·    ·");
+        assert_eq!(lines[5], "   ·    ·    · 999+");
+    }
+
+    #[test]
+    fn invalid_months_render_nothing() {
+        assert_eq!(render_calendar(2024, 13, &BTreeMap::new()), "");
+    }
+}
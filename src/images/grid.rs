@@ -0,0 +1,50 @@
+use image::imageops::FilterType;
+use image::DynamicImage;
+use image::ImageBuffer;
+use image::RgbaImage;
+use std::io::Cursor;
+
+pub const TILE_WIDTH: u32 = 64;
+pub const TILE_HEIGHT: u32 = 64;
+
+/// Lays album art out left to right, top to bottom, and encodes the grid as a PNG.
+/// Art that can't be decoded is skipped, so the next album takes its tile.
+pub fn render_grid(album_arts: Vec<Vec<u8>>, square_size: usize) -> Vec<u8> {
+    let mut grid_image: RgbaImage = ImageBuffer::new(
+        TILE_WIDTH * square_size as u32,
+        TILE_HEIGHT * square_size as u32,
+    );
+
+    let mut x = 0;
+    let mut y = 0;
+
+    let mut image_count = 0;
+
+    for album_art_bytes in album_arts {
+        if let Ok(img) = image::load_from_memory(&album_art_bytes) {
+            let mut image = DynamicImage::ImageRgba8(image::imageops::resize(
+                &img,
+                TILE_WIDTH,
+                TILE_HEIGHT,
+                FilterType::CatmullRom,
+            ));
+            image::imageops::overlay(&mut grid_image, image.as_mut_rgba8().unwrap(), x, y);
+            image_count += 1;
+            x += TILE_WIDTH as i64;
+            if image_count >= square_size {
+                x = 0;
+                y += TILE_HEIGHT as i64;
+                image_count = 0;
+            }
+        }
+    }
+
+    let mut grid_image_bytes: Vec<u8> = Vec::new();
+    grid_image
+        .write_to(
+            &mut Cursor::new(&mut grid_image_bytes),
+            image::ImageOutputFormat::Png,
+        )
+        .unwrap();
+    grid_image_bytes
+}
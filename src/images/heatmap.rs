@@ -0,0 +1,66 @@
+use image::ImageBuffer;
+use image::Rgba;
+use image::RgbaImage;
+use std::io::Cursor;
+
+pub const CELL_SIZE: u32 = 24;
+pub const CELL_GAP: u32 = 2;
+
+const BACKGROUND: [u8; 3] = [0x1e, 0x1f, 0x22];
+const EMPTY: [u8; 3] = [0x2b, 0x2d, 0x31];
+const FULL: [u8; 3] = [0x1d, 0xb9, 0x54];
+
+fn cell_color(count: u64, max: u64) -> Rgba<u8> {
+    if count == 0 || max == 0 {
+        return Rgba([EMPTY[0], EMPTY[1], EMPTY[2], 255]);
+    }
+    // square root so a few plays still show up next to the busiest hour
+    let t = (count as f64 / max as f64).sqrt();
+    let mix = |from: u8, to: u8| (from as f64 + (to as f64 - from as f64) * t).round() as u8;
+    Rgba([
+        mix(EMPTY[0], FULL[0]),
+        mix(EMPTY[1], FULL[1]),
+        mix(EMPTY[2], FULL[2]),
+        255,
+    ])
+}
+
+/// Draws `counts[weekday][hour]` as a PNG, one row per weekday from Monday down and one column per hour from midnight.
+pub fn render_heatmap(counts: &[[u64; 24]; 7]) -> Vec<u8> {
+    let max = counts
+        .iter()
+        .flat_map(|day| day.iter())
+        .copied()
+        .max()
+        .unwrap_or(0);
+
+    let width = CELL_GAP + 24 * (CELL_SIZE + CELL_GAP);
+    let height = CELL_GAP + 7 * (CELL_SIZE + CELL_GAP);
+    let mut heatmap_image: RgbaImage = ImageBuffer::from_pixel(
+        width,
+        height,
+        Rgba([BACKGROUND[0], BACKGROUND[1], BACKGROUND[2], 255]),
+    );
+
+    for (weekday, hours) in counts.iter().enumerate() {
+        for (hour, count) in hours.iter().enumerate() {
+            let color = cell_color(*count, max);
+            let left = CELL_GAP + hour as u32 * (CELL_SIZE + CELL_GAP);
+            let top = CELL_GAP + weekday as u32 * (CELL_SIZE + CELL_GAP);
+            for x in left..left + CELL_SIZE {
+                for y in top..top + CELL_SIZE {
+                    heatmap_image.put_pixel(x, y, color);
+                }
+            }
+        }
+    }
+
+    let mut heatmap_image_bytes: Vec<u8> = Vec::new();
+    heatmap_image
+        .write_to(
+            &mut Cursor::new(&mut heatmap_image_bytes),
+            image::ImageOutputFormat::Png,
+        )
+        .unwrap();
+    heatmap_image_bytes
+}
@@ -0,0 +1,311 @@
+use serde::{Deserialize as _, Deserializer};
+use serde_derive::{Deserialize, Serialize};
+
+/// Last.FM sends a bare object instead of a one element list when there's only one result.
+pub fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: serde::Deserialize<'de>,
+{
+    #[derive(serde_derive::Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        Many(Vec<T>),
+        One(T),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::Many(many) => many,
+        OneOrMany::One(one) => vec![one],
+    })
+}
+
+/// Last.FM reports failures with a 200 and this body, so every response is tried against it first.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum LastFMResponse<T> {
+    Error(LastFMApiError),
+    Ok(T),
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMApiError {
+    pub error: u32,
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMImage {
+    pub size: String,
+    #[serde(rename = "#text")]
+    pub url: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMArtistRef {
+    pub name: String,
+    #[serde(default)]
+    pub url: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMTopAlbum {
+    pub name: String,
+    pub artist: LastFMArtistRef,
+    pub playcount: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub image: Vec<LastFMImage>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMTopAlbumList {
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub album: Vec<LastFMTopAlbum>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMTopAlbumsResponse {
+    pub topalbums: LastFMTopAlbumList,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMTopArtist {
+    pub name: String,
+    pub playcount: String,
+    #[serde(default)]
+    pub url: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMTopArtistList {
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub artist: Vec<LastFMTopArtist>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMTopArtistsResponse {
+    pub topartists: LastFMTopArtistList,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMTopTrack {
+    pub name: String,
+    pub artist: LastFMArtistRef,
+    pub playcount: String,
+    #[serde(default)]
+    pub url: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMTopTrackList {
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub track: Vec<LastFMTopTrack>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMTopTracksResponse {
+    pub toptracks: LastFMTopTrackList,
+}
+
+/// `{"#text": ...}` objects, how `user.getRecentTracks` and the weekly charts name artists and albums.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMText {
+    #[serde(rename = "#text")]
+    pub text: String,
+}
+
+/// An album or track on a weekly chart. Unlike the top charts, these don't come with images.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMChartItem {
+    pub name: String,
+    pub artist: LastFMText,
+    pub playcount: String,
+    #[serde(default)]
+    pub url: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMWeeklyArtistChart {
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub artist: Vec<LastFMTopArtist>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMWeeklyArtistChartResponse {
+    pub weeklyartistchart: LastFMWeeklyArtistChart,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMWeeklyAlbumChart {
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub album: Vec<LastFMChartItem>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMWeeklyAlbumChartResponse {
+    pub weeklyalbumchart: LastFMWeeklyAlbumChart,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMWeeklyTrackChart {
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub track: Vec<LastFMChartItem>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMWeeklyTrackChartResponse {
+    pub weeklytrackchart: LastFMWeeklyTrackChart,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMAlbumInfo {
+    pub name: String,
+    #[serde(default)]
+    pub image: Vec<LastFMImage>,
+    /// Only present when a `username` was passed.
+    pub userplaycount: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMAlbumInfoResponse {
+    pub album: LastFMAlbumInfo,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMDate {
+    pub uts: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMTrackAttr {
+    pub nowplaying: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMRecentTrack {
+    pub name: String,
+    pub artist: LastFMText,
+    pub album: LastFMText,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub image: Vec<LastFMImage>,
+    /// Missing on the track currently playing.
+    pub date: Option<LastFMDate>,
+    #[serde(rename = "@attr")]
+    pub attr: Option<LastFMTrackAttr>,
+}
+
+impl LastFMRecentTrack {
+    pub fn now_playing(&self) -> bool {
+        self.attr.as_ref().and_then(|x| x.nowplaying.as_deref()) == Some("true")
+    }
+
+    pub fn timestamp(&self) -> Option<i64> {
+        self.date.as_ref().and_then(|x| x.uts.parse::<i64>().ok())
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMPageAttr {
+    #[serde(rename = "totalPages")]
+    pub total_pages: String,
+    pub total: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMRecentTracks {
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub track: Vec<LastFMRecentTrack>,
+    #[serde(rename = "@attr")]
+    pub attr: LastFMPageAttr,
+}
+
+impl LastFMRecentTracks {
+    pub fn total_pages(&self) -> usize {
+        self.attr.total_pages.parse::<usize>().unwrap_or(0)
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMRecentTracksResponse {
+    pub recenttracks: LastFMRecentTracks,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMRegistered {
+    pub unixtime: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMUserInfo {
+    pub name: String,
+    pub playcount: String,
+    #[serde(default)]
+    pub url: String,
+    pub registered: LastFMRegistered,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMUserInfoResponse {
+    pub user: LastFMUserInfo,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMArtistStats {
+    /// Only present when a `username` was passed, and left out entirely if they've never played the artist.
+    pub userplaycount: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMArtistInfo {
+    pub name: String,
+    #[serde(default)]
+    pub url: String,
+    pub stats: LastFMArtistStats,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMArtistInfoResponse {
+    pub artist: LastFMArtistInfo,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMTrackInfo {
+    pub name: String,
+    #[serde(default)]
+    pub url: String,
+    /// Only present when a `username` was passed.
+    pub userplaycount: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMTrackInfoResponse {
+    pub track: LastFMTrackInfo,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMTokenResponse {
+    pub token: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMSession {
+    pub name: String,
+    pub key: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct LastFMSessionResponse {
+    pub session: LastFMSession,
+}
+
+/// The largest image Last.FM has, if any. Last.FM returns empty URLs for missing art.
+pub fn largest_image(images: &[LastFMImage]) -> Option<String> {
+    images
+        .iter()
+        .rev()
+        .find(|image| !image.url.is_empty())
+        .map(|image| image.url.clone())
+}
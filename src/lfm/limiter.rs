@@ -0,0 +1,195 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// Last.FM allows around 5 requests per second per API key, averaged over a few minutes.
+pub const DEFAULT_BURST: f64 = 5.0;
+pub const DEFAULT_REQUESTS_PER_SEC: f64 = 4.0;
+/// How long the whole bucket stays empty after Last.FM tells us to slow down.
+pub const THROTTLE_PENALTY: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Keeps `queue_depth` right when a waiting request is cancelled, e.g. by the Cancel button.
+struct QueuedRequest<'a>(&'a AtomicUsize);
+
+impl<'a> QueuedRequest<'a> {
+    fn new(queue_depth: &'a AtomicUsize) -> Self {
+        queue_depth.fetch_add(1, Ordering::Relaxed);
+        QueuedRequest(queue_depth)
+    }
+}
+
+impl Drop for QueuedRequest<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LimiterMetrics {
+    pub queue_depth: usize,
+    pub requests: u64,
+    pub throttled: u64,
+}
+
+/// Token bucket shared by every Last.FM request. Callers wait for a token instead of failing.
+#[derive(Debug)]
+pub struct RateLimiter {
+    burst: f64,
+    requests_per_sec: f64,
+    // tokio's mutex is fair, so holding it while sleeping hands out tokens in arrival order
+    bucket: Mutex<Bucket>,
+    /// No tokens are handed out before this, set when Last.FM rate limits us.
+    /// Kept outside the bucket so it can be set without queueing behind waiters.
+    paused_until: std::sync::Mutex<Option<Instant>>,
+    queue_depth: AtomicUsize,
+    requests: AtomicU64,
+    throttled: AtomicU64,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter::new(DEFAULT_BURST, DEFAULT_REQUESTS_PER_SEC)
+    }
+}
+
+impl RateLimiter {
+    pub fn new(burst: f64, requests_per_sec: f64) -> Self {
+        RateLimiter {
+            burst,
+            requests_per_sec,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                last_refill: Instant::now(),
+            }),
+            paused_until: std::sync::Mutex::new(None),
+            queue_depth: AtomicUsize::new(0),
+            requests: AtomicU64::new(0),
+            throttled: AtomicU64::new(0),
+        }
+    }
+
+    /// Waits until a request may be sent.
+    pub async fn acquire(&self) {
+        let queued = QueuedRequest::new(&self.queue_depth);
+        let mut bucket = self.bucket.lock().await;
+        loop {
+            let paused_until = *self.paused_until.lock().unwrap();
+            if let Some(paused_until) = paused_until {
+                if paused_until > Instant::now() {
+                    tokio::time::sleep_until(paused_until).await;
+                    // start over from an empty bucket rather than refilling for the whole pause
+                    bucket.tokens = 0.0;
+                    bucket.last_refill = Instant::now();
+                    continue;
+                }
+            }
+
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * self.requests_per_sec).min(self.burst);
+            bucket.last_refill = now;
+
+            if bucket.tokens >= 1.0 {
+                bucket.tokens -= 1.0;
+                break;
+            }
+            let wait = (1.0 - bucket.tokens) / self.requests_per_sec;
+            tokio::time::sleep(Duration::from_secs_f64(wait)).await;
+        }
+        drop(bucket);
+        drop(queued);
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Called when Last.FM answers with a 429 or error 29, stops handing out tokens for [`THROTTLE_PENALTY`].
+    pub fn throttled(&self) {
+        self.throttled.fetch_add(1, Ordering::Relaxed);
+        log::warn!("Last.FM rate limited us, pausing Last.FM requests");
+        *self.paused_until.lock().unwrap() = Some(Instant::now() + THROTTLE_PENALTY);
+    }
+
+    pub fn metrics(&self) -> LimiterMetrics {
+        LimiterMetrics {
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+            requests: self.requests.load(Ordering::Relaxed),
+            throttled: self.throttled.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn burst_is_handed_out_at_once() {
+        let limiter = RateLimiter::new(3.0, 1.0);
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+        assert_eq!(limiter.metrics().requests, 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tokens_refill_over_time() {
+        let limiter = RateLimiter::new(2.0, 2.0);
+        limiter.acquire().await;
+        limiter.acquire().await;
+        tokio::time::advance(Duration::from_secs(1)).await;
+        let start = Instant::now();
+        limiter.acquire().await;
+        limiter.acquire().await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refill_stops_at_the_burst() {
+        let limiter = RateLimiter::new(2.0, 1.0);
+        tokio::time::advance(Duration::from_secs(60)).await;
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn throttling_pauses_every_request() {
+        let limiter = RateLimiter::new(5.0, 4.0);
+        limiter.throttled();
+        let start = Instant::now();
+        limiter.acquire().await;
+        assert!(start.elapsed() >= THROTTLE_PENALTY);
+        assert_eq!(limiter.metrics().throttled, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelled_requests_leave_the_queue() {
+        let limiter = RateLimiter::new(1.0, 1.0);
+        limiter.acquire().await;
+
+        let mut waiting = Box::pin(limiter.acquire());
+        assert!(futures::poll!(&mut waiting).is_pending());
+        assert_eq!(limiter.metrics().queue_depth, 1);
+        drop(waiting);
+        assert_eq!(
+            limiter.metrics(),
+            LimiterMetrics {
+                queue_depth: 0,
+                requests: 1,
+                throttled: 0,
+            }
+        );
+    }
+}
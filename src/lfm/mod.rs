@@ -1,8 +1,612 @@
-use lastfm::Client;
+pub mod json;
+pub mod limiter;
+pub mod sync;
 
-pub async fn get_lastfm_client(username: String, lastfm_api: String) -> Client<String, String> {
-    Client::builder()
-        .api_key(lastfm_api)
-        .username(username)
-        .build()
+use crate::lfm::json::*;
+use crate::lfm::limiter::RateLimiter;
+use hyper::client::connect::dns::Name;
+use md5::{Digest, Md5};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+pub const LASTFM_API_BASE: &str = "https://ws.audioscrobbler.com/2.0/";
+pub const LASTFM_AUTH_URL: &str = "https://www.last.fm/api/auth/";
+pub const LIBREFM_API_BASE: &str = "https://libre.fm/2.0/";
+/// Sent instead of the bot owner's key to Last.FM-compatible services. GNU FM doesn't check keys,
+/// and the owner's Last.FM key has no business going to another host.
+pub const GNUFM_API_KEY: &str = "00000000000000000000000000000000";
+
+/// Last.FM's error code for a user, artist, album or track that doesn't exist.
+pub const LASTFM_ERROR_NOT_FOUND: u32 = 6;
+/// Returned by `auth.getSession` until the user has approved the token.
+pub const LASTFM_ERROR_UNAUTHORIZED_TOKEN: u32 = 14;
+/// Last.FM's "rate limit exceeded" error code.
+pub const LASTFM_ERROR_RATE_LIMITED: u32 = 29;
+/// The most scrobbles `user.getRecentTracks` returns per page.
+pub const RECENT_TRACKS_PAGE_SIZE: usize = 200;
+
+#[derive(Debug)]
+pub enum LastFMError {
+    Request(reqwest::Error),
+    Api(LastFMApiError),
+}
+
+impl fmt::Display for LastFMError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LastFMError::Request(err) => write!(f, "{}", err),
+            LastFMError::Api(err) => write!(f, "Last.FM error {}: {}", err.error, err.message),
+        }
+    }
+}
+
+impl std::error::Error for LastFMError {}
+
+impl From<reqwest::Error> for LastFMError {
+    fn from(err: reqwest::Error) -> Self {
+        LastFMError::Request(err)
+    }
+}
+
+impl LastFMError {
+    pub fn not_found(&self) -> bool {
+        self.code() == Some(LASTFM_ERROR_NOT_FOUND)
+    }
+
+    pub fn code(&self) -> Option<u32> {
+        match self {
+            LastFMError::Api(err) => Some(err.error),
+            LastFMError::Request(_) => None,
+        }
+    }
+}
+
+/// Whether `address` is somewhere on the internet, rather than loopback, a private network,
+/// link-local, carrier-grade NAT or otherwise reserved.
+pub fn is_public_address(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            let [a, b, ..] = address.octets();
+            !(address.is_private()
+                || address.is_loopback()
+                || address.is_link_local()
+                || address.is_unspecified()
+                || address.is_broadcast()
+                || address.is_documentation()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(address) => match address.to_ipv4_mapped() {
+            Some(mapped) => is_public_address(&IpAddr::V4(mapped)),
+            None => {
+                let first = address.segments()[0];
+                !(address.is_loopback()
+                    || address.is_unspecified()
+                    // unique local fc00::/7 and link-local fe80::/10
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Resolves hosts for the client that talks to other services and leaves out every address that isn't public.
+/// `check_service_url` only runs at setup, this runs on every connection, so a GNU FM server's name can't
+/// pass setup and later point at the bot's own network.
+struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> reqwest::dns::Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|address| is_public_address(&address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(format!("{} has no public addresses", host).into());
+            }
+            let addresses: reqwest::dns::Addrs = Box::new(addresses.into_iter());
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(addresses)
+        })
+    }
+}
+
+/// The bot's own Last.FM client, built on reqwest like `user_get_top_albums` always was.
+/// The same client talks to Libre.fm and other GNU FM servers with their own key and rate limit.
+#[derive(Clone, Debug)]
+pub struct LastFMApi {
+    pub client: reqwest::Client,
+    pub api_key: String,
+    /// Only needed for authenticated methods, i.e. verifying account ownership.
+    pub api_secret: Option<String>,
+    pub api_base: String,
+    pub auth_url: String,
+    /// Shared by every clone pointed at the same service, so each service has its own request budget
+    /// and one slow GNU FM server can't hold up Last.FM.
+    pub limiter: Arc<RateLimiter>,
+    /// API base URL of the Last.FM-compatible service this talks to, `None` for Last.FM itself.
+    pub service: Option<String>,
+    /// The limiters of every other service this has been pointed at, by API base URL.
+    service_limiters: Arc<Mutex<HashMap<String, Arc<RateLimiter>>>>,
+    /// Used for other services instead of `client`. It doesn't follow redirects and only connects
+    /// to public addresses, so a server a user pointed the bot at can't send it somewhere else.
+    service_client: reqwest::Client,
+}
+
+impl LastFMApi {
+    pub fn new(
+        client: reqwest::Client,
+        api_key: String,
+        api_secret: Option<String>,
+        api_base: Option<String>,
+        auth_url: Option<String>,
+    ) -> Self {
+        LastFMApi {
+            client,
+            api_key,
+            api_secret,
+            api_base: api_base.unwrap_or(LASTFM_API_BASE.to_string()),
+            auth_url: auth_url.unwrap_or(LASTFM_AUTH_URL.to_string()),
+            limiter: Arc::new(RateLimiter::default()),
+            service: None,
+            service_limiters: Arc::default(),
+            service_client: reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .dns_resolver(Arc::new(PublicResolver))
+                .build()
+                .unwrap(),
+        }
+    }
+
+    /// The same API pointed at a Last.FM-compatible service like Libre.fm or a GNU FM server.
+    /// GNU FM serves web authentication at `/api/auth/` next to its `/2.0/` API.
+    /// The owner's key and secret stay with Last.FM, and every service gets its own rate limiter.
+    pub fn for_service(&self, service: Option<String>) -> LastFMApi {
+        match service {
+            Some(api_base) => {
+                let root = api_base
+                    .trim_end_matches('/')
+                    .trim_end_matches("2.0")
+                    .trim_end_matches('/')
+                    .to_string();
+                let limiter = self
+                    .service_limiters
+                    .lock()
+                    .unwrap()
+                    .entry(api_base.clone())
+                    .or_default()
+                    .clone();
+                LastFMApi {
+                    client: self.service_client.clone(),
+                    api_key: GNUFM_API_KEY.to_string(),
+                    api_secret: None,
+                    api_base: format!("{}/", api_base.trim_end_matches('/')),
+                    auth_url: format!("{}/api/auth/", root),
+                    limiter,
+                    service: Some(api_base),
+                    ..self.clone()
+                }
+            }
+            None => self.clone(),
+        }
+    }
+
+    /// Checks a GNU FM API URL a user gave before the bot sends anything to it.
+    /// It has to be http(s) and resolve only to public addresses, so nobody can point the bot at its own network.
+    pub async fn check_service_url(api_url: &str) -> Result<(), &'static str> {
+        let url = url::Url::parse(api_url).map_err(|_| "That isn't a valid URL.")?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err("The API URL has to start with `http://` or `https://`.");
+        }
+        let host = url
+            .host_str()
+            .ok_or("The API URL has no server in it.")?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let port = url.port_or_known_default().unwrap_or(443);
+        let addresses: Vec<IpAddr> = tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|_| "Couldn't find that server.")?
+            .map(|address| address.ip())
+            .collect();
+        match !addresses.is_empty() && addresses.iter().all(is_public_address) {
+            true => Ok(()),
+            false => Err("The API URL has to point at a public server."),
+        }
+    }
+
+    /// How scrobbles from this service are told apart in the cache, empty for Last.FM itself.
+    pub fn service_key(&self) -> String {
+        self.service.clone().unwrap_or_default()
+    }
+
+    /// Human readable service name for embeds.
+    pub fn service_name(&self) -> String {
+        match &self.service {
+            None => "Last.FM".to_string(),
+            Some(api_base) if api_base == LIBREFM_API_BASE => "Libre.fm".to_string(),
+            Some(api_base) => url::Url::parse(api_base)
+                .ok()
+                .and_then(|x| x.host_str().map(|x| x.to_string()))
+                .unwrap_or(api_base.clone()),
+        }
+    }
+
+    /// `api_sig` as described in Last.FM's authentication spec: every parameter sorted by name,
+    /// concatenated as name then value, followed by the secret, md5 hashed.
+    pub fn sign(&self, params: &[(&str, &str)], secret: &str) -> String {
+        let mut sorted = params.to_vec();
+        sorted.sort_by(|a, b| a.0.cmp(b.0));
+        let mut hasher = Md5::new();
+        for (name, value) in sorted {
+            hasher.update(name.as_bytes());
+            hasher.update(value.as_bytes());
+        }
+        hasher.update(secret.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Calls a Last.FM API method. `api_key` and `format` are added to `params`,
+    /// and if `signed` is set so is `api_sig`. Waits for the rate limiter first.
+    pub async fn get<T: DeserializeOwned>(
+        &self,
+        params: &[(&str, &str)],
+        signed: bool,
+    ) -> Result<T, LastFMError> {
+        let api_sig;
+        let mut params = params.to_vec();
+        params.push(("api_key", &self.api_key));
+        if signed {
+            api_sig = self.sign(
+                &params,
+                self.api_secret
+                    .as_deref()
+                    .expect("No Last.FM API secret passed"),
+            );
+            params.push(("api_sig", &api_sig));
+        }
+        params.push(("format", "json"));
+
+        self.limiter.acquire().await;
+        let response = self
+            .client
+            .get(&self.api_base)
+            .query(&params)
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            self.limiter.throttled();
+        }
+        match response.json::<LastFMResponse<T>>().await? {
+            LastFMResponse::Ok(ok) => Ok(ok),
+            LastFMResponse::Error(err) => {
+                if err.error == LASTFM_ERROR_RATE_LIMITED {
+                    self.limiter.throttled();
+                }
+                Err(LastFMError::Api(err))
+            }
+        }
+    }
+
+    /// `period` is one of Last.FM's chart periods: `overall`, `7day`, `1month`, `3month`, `6month` or `12month`.
+    pub async fn user_get_top_albums(
+        &self,
+        username: String,
+        period: String,
+        limit: usize,
+    ) -> Result<Vec<LastFMTopAlbum>, LastFMError> {
+        self.get::<LastFMTopAlbumsResponse>(
+            &[
+                ("method", "user.gettopalbums"),
+                ("user", &username),
+                ("period", &period),
+                ("limit", &limit.to_string()),
+            ],
+            false,
+        )
+        .await
+        .map(|x| x.topalbums.album)
+    }
+
+    pub async fn user_get_top_artists(
+        &self,
+        username: String,
+        period: String,
+        limit: usize,
+    ) -> Result<Vec<LastFMTopArtist>, LastFMError> {
+        self.get::<LastFMTopArtistsResponse>(
+            &[
+                ("method", "user.gettopartists"),
+                ("user", &username),
+                ("period", &period),
+                ("limit", &limit.to_string()),
+            ],
+            false,
+        )
+        .await
+        .map(|x| x.topartists.artist)
+    }
+
+    pub async fn user_get_top_tracks(
+        &self,
+        username: String,
+        period: String,
+        limit: usize,
+    ) -> Result<Vec<LastFMTopTrack>, LastFMError> {
+        self.get::<LastFMTopTracksResponse>(
+            &[
+                ("method", "user.gettoptracks"),
+                ("user", &username),
+                ("period", &period),
+                ("limit", &limit.to_string()),
+            ],
+            false,
+        )
+        .await
+        .map(|x| x.toptracks.track)
+    }
+
+    /// The weekly chart methods take any `from` and `to`, not just the weeks `user.getWeeklyChartList` lists.
+    pub async fn user_get_weekly_artist_chart(
+        &self,
+        username: String,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<LastFMTopArtist>, LastFMError> {
+        self.get::<LastFMWeeklyArtistChartResponse>(
+            &[
+                ("method", "user.getweeklyartistchart"),
+                ("user", &username),
+                ("from", &from.to_string()),
+                ("to", &to.to_string()),
+            ],
+            false,
+        )
+        .await
+        .map(|x| x.weeklyartistchart.artist)
+    }
+
+    pub async fn user_get_weekly_album_chart(
+        &self,
+        username: String,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<LastFMChartItem>, LastFMError> {
+        self.get::<LastFMWeeklyAlbumChartResponse>(
+            &[
+                ("method", "user.getweeklyalbumchart"),
+                ("user", &username),
+                ("from", &from.to_string()),
+                ("to", &to.to_string()),
+            ],
+            false,
+        )
+        .await
+        .map(|x| x.weeklyalbumchart.album)
+    }
+
+    pub async fn user_get_weekly_track_chart(
+        &self,
+        username: String,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<LastFMChartItem>, LastFMError> {
+        self.get::<LastFMWeeklyTrackChartResponse>(
+            &[
+                ("method", "user.getweeklytrackchart"),
+                ("user", &username),
+                ("from", &from.to_string()),
+                ("to", &to.to_string()),
+            ],
+            false,
+        )
+        .await
+        .map(|x| x.weeklytrackchart.track)
+    }
+
+    /// One page of `username`'s scrobbles, newest first. `from` and `to` are inclusive unix timestamps.
+    /// The currently playing track, if any, comes before the scrobbles on the first page.
+    pub async fn user_get_recent_tracks(
+        &self,
+        username: String,
+        from: Option<i64>,
+        to: Option<i64>,
+        page: usize,
+        limit: usize,
+    ) -> Result<LastFMRecentTracks, LastFMError> {
+        let from = from.map(|x| x.to_string());
+        let to = to.map(|x| x.to_string());
+        let page = page.to_string();
+        let limit = limit.min(RECENT_TRACKS_PAGE_SIZE).to_string();
+        let mut params = vec![
+            ("method", "user.getrecenttracks"),
+            ("user", username.as_str()),
+            ("page", page.as_str()),
+            ("limit", limit.as_str()),
+        ];
+        if let Some(from) = &from {
+            params.push(("from", from));
+        }
+        if let Some(to) = &to {
+            params.push(("to", to));
+        }
+        self.get::<LastFMRecentTracksResponse>(&params, false)
+            .await
+            .map(|x| x.recenttracks)
+    }
+
+    pub async fn user_get_info(&self, username: String) -> Result<LastFMUserInfo, LastFMError> {
+        self.get::<LastFMUserInfoResponse>(
+            &[("method", "user.getinfo"), ("user", &username)],
+            false,
+        )
+        .await
+        .map(|x| x.user)
+    }
+
+    pub async fn album_get_info(
+        &self,
+        artist: String,
+        album: String,
+    ) -> Result<LastFMAlbumInfo, LastFMError> {
+        self.get::<LastFMAlbumInfoResponse>(
+            &[
+                ("method", "album.getinfo"),
+                ("artist", &artist),
+                ("album", &album),
+                ("autocorrect", "1"),
+            ],
+            false,
+        )
+        .await
+        .map(|x| x.album)
+    }
+
+    /// How many times `username` has scrobbled an album.
+    pub async fn album_get_user_playcount(
+        &self,
+        artist: String,
+        album: String,
+        username: String,
+    ) -> Result<u64, LastFMError> {
+        self.get::<LastFMAlbumInfoResponse>(
+            &[
+                ("method", "album.getinfo"),
+                ("artist", &artist),
+                ("album", &album),
+                ("username", &username),
+                ("autocorrect", "1"),
+            ],
+            false,
+        )
+        .await
+        .map(|x| {
+            x.album
+                .userplaycount
+                .and_then(|count| count.parse::<u64>().ok())
+                .unwrap_or(0)
+        })
+    }
+
+    /// How many times `username` has scrobbled `artist`. Last.FM's autocorrect handles casing and common misspellings.
+    pub async fn artist_get_user_playcount(
+        &self,
+        artist: String,
+        username: String,
+    ) -> Result<u64, LastFMError> {
+        self.get::<LastFMArtistInfoResponse>(
+            &[
+                ("method", "artist.getinfo"),
+                ("artist", &artist),
+                ("username", &username),
+                ("autocorrect", "1"),
+            ],
+            false,
+        )
+        .await
+        .map(|x| {
+            x.artist
+                .stats
+                .userplaycount
+                .and_then(|count| count.parse::<u64>().ok())
+                .unwrap_or(0)
+        })
+    }
+
+    /// How many times `username` has scrobbled a track.
+    pub async fn track_get_user_playcount(
+        &self,
+        artist: String,
+        track: String,
+        username: String,
+    ) -> Result<u64, LastFMError> {
+        self.get::<LastFMTrackInfoResponse>(
+            &[
+                ("method", "track.getinfo"),
+                ("artist", &artist),
+                ("track", &track),
+                ("username", &username),
+                ("autocorrect", "1"),
+            ],
+            false,
+        )
+        .await
+        .map(|x| {
+            x.track
+                .userplaycount
+                .and_then(|count| count.parse::<u64>().ok())
+                .unwrap_or(0)
+        })
+    }
+
+    pub async fn auth_get_token(&self) -> Result<String, LastFMError> {
+        self.get::<LastFMTokenResponse>(&[("method", "auth.gettoken")], true)
+            .await
+            .map(|x| x.token)
+    }
+
+    /// Where the user approves `token`.
+    pub fn auth_url_for(&self, token: &str) -> String {
+        format!("{}?api_key={}&token={}", self.auth_url, self.api_key, token)
+    }
+
+    /// Fails with [`LASTFM_ERROR_UNAUTHORIZED_TOKEN`] until the user has approved `token`.
+    pub async fn auth_get_session(&self, token: String) -> Result<LastFMSession, LastFMError> {
+        self.get::<LastFMSessionResponse>(&[("method", "auth.getsession"), ("token", &token)], true)
+            .await
+            .map(|x| x.session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn private_addresses_are_not_public() {
+        for address in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_address(&address.parse().unwrap()), "{}", address);
+        }
+    }
+
+    #[test]
+    fn internet_addresses_are_public() {
+        for address in ["1.1.1.1", "93.184.216.34", "2606:4700:4700::1111"] {
+            assert!(is_public_address(&address.parse().unwrap()), "{}", address);
+        }
+    }
+
+    #[test]
+    fn other_services_get_their_own_key_and_limiter() {
+        let lastfm = LastFMApi::new(
+            reqwest::Client::new(),
+            "owner key".to_string(),
+            Some("owner secret".to_string()),
+            None,
+            None,
+        );
+        let librefm = lastfm.for_service(Some(LIBREFM_API_BASE.to_string()));
+        assert_eq!(librefm.api_key, GNUFM_API_KEY);
+        assert_eq!(librefm.api_secret, None);
+        assert!(!Arc::ptr_eq(&librefm.limiter, &lastfm.limiter));
+        let again = lastfm.for_service(Some(LIBREFM_API_BASE.to_string()));
+        assert!(Arc::ptr_eq(&librefm.limiter, &again.limiter));
+        assert_eq!(librefm.auth_url, "https://libre.fm/api/auth/");
+    }
 }
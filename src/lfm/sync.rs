@@ -0,0 +1,248 @@
+use crate::db::postgres::*;
+use crate::discord::milestones::check_milestone;
+use crate::lfm::json::{LastFMRecentTrack, LastFMRecentTracks};
+use crate::lfm::{LastFMApi, LastFMError, RECENT_TRACKS_PAGE_SIZE};
+use futures_util::stream::StreamExt;
+use serenity::all::Http;
+use sqlx::PgPool;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// How long the worker waits between passes over `lastfm_usernames`.
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// How many users are synced with Last.FM at the same time.
+pub const MAX_CONCURRENT_SYNCS: usize = 4;
+/// Backoff after the first failure, doubled with every failure after it.
+pub const BASE_BACKOFF_SECS: i64 = 5 * 60;
+pub const MAX_BACKOFF_SECS: i64 = 24 * 60 * 60;
+
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|x| x.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+pub fn backoff_secs(failures: i32) -> i64 {
+    if failures <= 0 {
+        return 0;
+    }
+    BASE_BACKOFF_SECS
+        .saturating_mul(1 << (failures - 1).min(20))
+        .min(MAX_BACKOFF_SECS)
+}
+
+/// When the worker should try `status` again, `None` if it's due now.
+pub fn next_attempt(status: &LastFMSyncStatus) -> Option<i64> {
+    let failures = status.failures.unwrap_or(0);
+    match (failures, status.last_attempt) {
+        (0, _) | (_, None) => None,
+        (failures, Some(last_attempt)) => Some(last_attempt + backoff_secs(failures)),
+    }
+}
+
+/// The currently playing track has no timestamp and isn't a scrobble yet, so it gives `None`.
+pub fn recent_track_to_scrobble(
+    service: String,
+    username: String,
+    track: LastFMRecentTrack,
+) -> Option<LastFMScrobble> {
+    let scrobbled_at = track.timestamp()?;
+    Some(LastFMScrobble {
+        service: Some(service),
+        lastfm_username: Some(username),
+        scrobbled_at: Some(scrobbled_at),
+        artist: Some(track.artist.text),
+        album: Some(track.album.text),
+        track: Some(track.name),
+        url: Some(track.url),
+    })
+}
+
+/// Stores every scrobble of `username` after `from` in the cache, one page at a time, oldest page first.
+/// Going oldest first means a sync that stops partway leaves no gap behind the newest cached scrobble,
+/// so the next one carries on from there. `on_page` is called with the running total after each page.
+/// Returns how many scrobbles were stored.
+pub async fn store_lastfm_scrobbles<F, Fut>(
+    pool: &PgPool,
+    lastfm: &LastFMApi,
+    username: String,
+    from: Option<i64>,
+    mut on_page: F,
+) -> Result<usize, LastFMError>
+where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let cache_key = username.to_lowercase();
+    // pin the upper end, otherwise new scrobbles shift every page after the first
+    let to = Some(unix_now());
+    let to_scrobbles = |recent: LastFMRecentTracks| -> Vec<LastFMScrobble> {
+        recent
+            .track
+            .into_iter()
+            .filter_map(|track| {
+                recent_track_to_scrobble(lastfm.service_key(), cache_key.clone(), track)
+            })
+            .collect()
+    };
+
+    // the first page is the newest, and the only way to learn how many pages there are
+    let newest = lastfm
+        .user_get_recent_tracks(username.clone(), from, to, 1, RECENT_TRACKS_PAGE_SIZE)
+        .await?;
+    let total_pages = newest.total_pages();
+    let mut stored = 0;
+    for page in (2..=total_pages).rev() {
+        let recent = lastfm
+            .user_get_recent_tracks(username.clone(), from, to, page, RECENT_TRACKS_PAGE_SIZE)
+            .await?;
+        let scrobbles = to_scrobbles(recent);
+        stored += scrobbles.len();
+        insert_lastfm_scrobbles(pool, scrobbles).await;
+        on_page(stored).await;
+    }
+    let scrobbles = to_scrobbles(newest);
+    stored += scrobbles.len();
+    insert_lastfm_scrobbles(pool, scrobbles).await;
+    on_page(stored).await;
+    Ok(stored)
+}
+
+/// Fetches every scrobble newer than the latest cached one for `username` and stores it.
+/// Returns how many scrobbles were added.
+pub async fn sync_lastfm_user(
+    pool: &PgPool,
+    lastfm: &LastFMApi,
+    username: String,
+) -> Result<usize, LastFMError> {
+    let cache_key = username.to_lowercase();
+    let latest_cached =
+        get_latest_lastfm_scrobble_time(pool, lastfm.service_key(), cache_key).await;
+
+    store_lastfm_scrobbles(
+        pool,
+        lastfm,
+        username,
+        latest_cached.map(|x| x + 1),
+        |_| async {},
+    )
+    .await
+}
+
+/// Syncs `username` and records how it went, `true` if it worked.
+async fn sync_and_record(pool: &PgPool, lastfm: &LastFMApi, username: String) -> bool {
+    let cache_key = username.to_lowercase();
+    let now = unix_now();
+    match sync_lastfm_user(pool, lastfm, username).await {
+        Ok(added) => {
+            if added > 0 {
+                log::info!(
+                    "Synced {} new scrobbles for {} user {}",
+                    added,
+                    lastfm.service_name(),
+                    cache_key
+                );
+            }
+            record_lastfm_sync_success(pool, lastfm.service_key(), cache_key, now).await;
+            true
+        }
+        Err(err) => {
+            log::warn!(
+                "Background sync failed for {} user {}: {}",
+                lastfm.service_name(),
+                cache_key,
+                err
+            );
+            record_lastfm_sync_failure(pool, lastfm.service_key(), cache_key, now, err.to_string())
+                .await;
+            false
+        }
+    }
+}
+
+/// Keeps the scrobble cache of every linked Last.FM user current, so commands rarely have to sync themselves.
+/// Milestones are checked after every sync, since the cache then holds each member's whole history.
+pub async fn run_sync_worker(pool: PgPool, lastfm: LastFMApi, http: Arc<Http>) {
+    log::info!("Starting Last.FM sync worker");
+    loop {
+        let now = unix_now();
+        // each account to sync, with every member who linked it
+        let mut due: Vec<(LastFMApi, String, Vec<String>)> = vec![];
+        for row in get_all_lastfm_usernames(&pool).await {
+            if let (Some(username), Some(member)) = (row.lastfm_username, row.discord_username) {
+                let service = lastfm.for_service(row.service);
+                let backed_off =
+                    get_lastfm_sync_status(&pool, service.service_key(), username.to_lowercase())
+                        .await
+                        .first()
+                        .and_then(next_attempt)
+                        .is_some_and(|next| next > now);
+                // usernames are case-insensitive, the same account linked twice is synced once
+                let duplicate = due.iter_mut().find(|(other, other_username, _)| {
+                    other.service == service.service
+                        && other_username.to_lowercase() == username.to_lowercase()
+                });
+                match duplicate {
+                    Some((_, _, members)) => members.push(member),
+                    None if !backed_off => due.push((service, username, vec![member])),
+                    None => {}
+                }
+            }
+        }
+
+        let pool_ref = &pool;
+        let http_ref = &http;
+        futures::stream::iter(due)
+            .for_each_concurrent(
+                MAX_CONCURRENT_SYNCS,
+                |(service, username, members)| async move {
+                    if !sync_and_record(pool_ref, &service, username.clone()).await {
+                        return;
+                    }
+                    let total = count_lastfm_scrobbles(
+                        pool_ref,
+                        service.service_key(),
+                        username.to_lowercase(),
+                    )
+                    .await;
+                    for member in members {
+                        check_milestone(http_ref, pool_ref, member, None, total.max(0) as u64)
+                            .await;
+                    }
+                },
+            )
+            .await;
+
+        let metrics = lastfm.limiter.metrics();
+        log::info!(
+            "Last.FM sync pass done. {} requests sent, {} waiting, {} rate limited responses so far",
+            metrics.requests,
+            metrics.queue_depth,
+            metrics.throttled
+        );
+
+        tokio::time::sleep(SYNC_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_backoff_without_failures() {
+        assert_eq!(backoff_secs(0), 0);
+        assert_eq!(backoff_secs(-1), 0);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        assert_eq!(backoff_secs(1), BASE_BACKOFF_SECS);
+        assert_eq!(backoff_secs(2), BASE_BACKOFF_SECS * 2);
+        assert_eq!(backoff_secs(4), BASE_BACKOFF_SECS * 8);
+        assert_eq!(backoff_secs(9), MAX_BACKOFF_SECS);
+        assert_eq!(backoff_secs(i32::MAX), MAX_BACKOFF_SECS);
+    }
+}
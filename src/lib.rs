@@ -1,7 +1,13 @@
+pub mod charts;
 pub mod db;
 pub mod discord;
+pub mod history;
 pub mod hos;
+pub mod images;
 pub mod lfm;
+pub mod listenbrainz;
+pub mod normalize;
+pub mod range;
 
 pub fn generate_api_key() -> String {
     use prefixed_api_key::PrefixedApiKeyController;
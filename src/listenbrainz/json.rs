@@ -0,0 +1,119 @@
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct ListenCount {
+    pub count: u64,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct ListenCountResponse {
+    pub payload: ListenCount,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct MbidMapping {
+    pub release_mbid: Option<String>,
+}
+
+/// Whatever else the submitting player sent. Players report a track's length in either field, or neither.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct AdditionalInfo {
+    pub duration_ms: Option<u64>,
+    pub duration: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct TrackMetadata {
+    pub artist_name: String,
+    pub track_name: String,
+    pub release_name: Option<String>,
+    pub mbid_mapping: Option<MbidMapping>,
+    #[serde(default)]
+    pub additional_info: Option<AdditionalInfo>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct Listen {
+    pub listened_at: i64,
+    pub track_metadata: TrackMetadata,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct Listens {
+    pub listens: Vec<Listen>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct ListensResponse {
+    pub payload: Listens,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct PlayingNow {
+    pub track_metadata: TrackMetadata,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct PlayingNowListens {
+    pub listens: Vec<PlayingNow>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct PlayingNowResponse {
+    pub payload: PlayingNowListens,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct ArtistStat {
+    pub artist_name: String,
+    pub listen_count: u64,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct ArtistStats {
+    pub artists: Vec<ArtistStat>,
+    pub total_artist_count: u64,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct ArtistStatsResponse {
+    pub payload: ArtistStats,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct ReleaseStat {
+    pub artist_name: String,
+    pub release_name: String,
+    pub listen_count: u64,
+    pub release_mbid: Option<String>,
+    /// The release Cover Art Archive actually has art for, which may be another release in the group.
+    pub caa_release_mbid: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct ReleaseStats {
+    pub releases: Vec<ReleaseStat>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct ReleaseStatsResponse {
+    pub payload: ReleaseStats,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct RecordingStat {
+    pub artist_name: String,
+    pub track_name: String,
+    pub release_name: Option<String>,
+    pub listen_count: u64,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct RecordingStats {
+    pub recordings: Vec<RecordingStat>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct RecordingStatsResponse {
+    pub payload: RecordingStats,
+}
@@ -0,0 +1,317 @@
+pub mod json;
+
+use crate::listenbrainz::json::*;
+use crate::normalize::credits;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use std::fmt;
+
+pub const LISTENBRAINZ_API_BASE: &str = "https://api.listenbrainz.org/1/";
+pub const COVER_ART_ARCHIVE_BASE: &str = "https://coverartarchive.org/";
+/// The most listens or stats entries ListenBrainz returns per request.
+pub const LISTENBRAINZ_MAX_ITEMS: usize = 1000;
+
+#[derive(Debug)]
+pub enum ListenBrainzError {
+    Request(reqwest::Error),
+    UserNotFound,
+    /// ListenBrainz answers 204 until it has calculated a user's statistics.
+    StatsNotReady,
+}
+
+impl fmt::Display for ListenBrainzError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenBrainzError::Request(err) => write!(f, "{}", err),
+            ListenBrainzError::UserNotFound => write!(f, "ListenBrainz user not found"),
+            ListenBrainzError::StatsNotReady => {
+                write!(f, "ListenBrainz hasn't calculated statistics yet")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ListenBrainzError {}
+
+impl From<reqwest::Error> for ListenBrainzError {
+    fn from(err: reqwest::Error) -> Self {
+        ListenBrainzError::Request(err)
+    }
+}
+
+/// Everything but letters, digits, `-`, `_` and `~` is escaped in a username,
+/// so no name can reach a different endpoint.
+const USERNAME_ESCAPE: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'~');
+
+/// `user/<username>/<endpoint>`, with the username escaped.
+fn user_path(username: &str, endpoint: &str) -> String {
+    format!(
+        "user/{}/{}",
+        utf8_percent_encode(username, USERNAME_ESCAPE),
+        endpoint
+    )
+}
+
+/// `false` for names that are empty or would still be read as `.` or `..` path segments after escaping.
+pub fn is_valid_username(username: &str) -> bool {
+    !username.is_empty() && username.chars().any(|c| c != '.')
+}
+
+#[derive(Clone, Debug)]
+pub struct ListenBrainzUser {
+    pub username: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct ListenBrainzApi {
+    pub client: reqwest::Client,
+    pub api_base: String,
+}
+
+impl ListenBrainzApi {
+    pub fn new(client: reqwest::Client, api_base: Option<String>) -> Self {
+        ListenBrainzApi {
+            client,
+            api_base: api_base.unwrap_or(LISTENBRAINZ_API_BASE.to_string()),
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: String,
+        params: &[(&str, &str)],
+    ) -> Result<T, ListenBrainzError> {
+        let response = self
+            .client
+            .get(format!("{}{}", self.api_base, path))
+            .query(params)
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Err(ListenBrainzError::UserNotFound),
+            StatusCode::NO_CONTENT => Err(ListenBrainzError::StatsNotReady),
+            _ => Ok(response.error_for_status()?.json::<T>().await?),
+        }
+    }
+
+    pub async fn listen_count(&self, username: String) -> Result<u64, ListenBrainzError> {
+        self.get::<ListenCountResponse>(user_path(&username, "listen-count"), &[])
+            .await
+            .map(|x| x.payload.count)
+    }
+
+    /// What the user's player reported as playing right now, if anything.
+    pub async fn playing_now(
+        &self,
+        username: String,
+    ) -> Result<Option<PlayingNow>, ListenBrainzError> {
+        self.get::<PlayingNowResponse>(user_path(&username, "playing-now"), &[])
+            .await
+            .map(|x| x.payload.listens.into_iter().next())
+    }
+
+    /// The latest `count` listens, newest first.
+    pub async fn latest_listens(
+        &self,
+        username: String,
+        count: usize,
+    ) -> Result<Vec<Listen>, ListenBrainzError> {
+        self.get::<ListensResponse>(
+            user_path(&username, "listens"),
+            &[("count", &count.min(LISTENBRAINZ_MAX_ITEMS).to_string())],
+        )
+        .await
+        .map(|x| x.payload.listens)
+    }
+
+    /// Every listen between `start` and `end`, newest first.
+    /// ListenBrainz only takes one of `min_ts` and `max_ts`, so this pages backwards from `end` until it passes `start`.
+    /// `max_ts` is exclusive, so each page asks for the oldest second of the last one again,
+    /// otherwise listens sharing that second with the end of a page would be skipped.
+    pub async fn listens(
+        &self,
+        username: String,
+        start: Option<i64>,
+        end: Option<i64>,
+    ) -> Result<Vec<Listen>, ListenBrainzError> {
+        let count = LISTENBRAINZ_MAX_ITEMS.to_string();
+        let mut max_ts = end.map(|x| x + 1);
+        let mut ret = vec![];
+        // the listens in the oldest second of the last page, they come back at the start of the next one
+        let mut boundary: Vec<Listen> = vec![];
+        loop {
+            let max_ts_param = max_ts.map(|x| x.to_string());
+            let mut params = vec![("count", count.as_str())];
+            if let Some(max_ts_param) = &max_ts_param {
+                params.push(("max_ts", max_ts_param));
+            }
+            let listens = self
+                .get::<ListensResponse>(user_path(&username, "listens"), &params)
+                .await?
+                .payload
+                .listens;
+
+            let Some(oldest) = listens.last().map(|x| x.listened_at) else {
+                break;
+            };
+            let page_len = listens.len();
+            let mut seen = std::mem::replace(
+                &mut boundary,
+                listens
+                    .iter()
+                    .filter(|x| x.listened_at == oldest)
+                    .cloned()
+                    .collect(),
+            );
+            let new_listens: Vec<Listen> = listens
+                .into_iter()
+                .filter(|x| match seen.iter().position(|y| y == x) {
+                    Some(i) => {
+                        seen.swap_remove(i);
+                        false
+                    }
+                    None => true,
+                })
+                .collect();
+            let progressed = !new_listens.is_empty();
+            ret.extend(
+                new_listens
+                    .into_iter()
+                    .filter(|x| start.map_or(true, |start| x.listened_at >= start)),
+            );
+            if start.is_some_and(|start| oldest < start) || page_len < LISTENBRAINZ_MAX_ITEMS {
+                break;
+            }
+            // a whole page in one second can't be paged through, move past it rather than loop
+            max_ts = Some(match progressed {
+                true => oldest + 1,
+                false => oldest,
+            });
+        }
+        Ok(ret)
+    }
+
+    /// `range` is one of ListenBrainz's stats ranges, i.e. `this_week`, `this_year` or `all_time`.
+    pub async fn top_artists(
+        &self,
+        username: String,
+        range: String,
+        count: usize,
+        offset: usize,
+    ) -> Result<ArtistStats, ListenBrainzError> {
+        self.get::<ArtistStatsResponse>(
+            format!("stats/{}", user_path(&username, "artists")),
+            &[
+                ("range", &range),
+                ("count", &count.to_string()),
+                ("offset", &offset.to_string()),
+            ],
+        )
+        .await
+        .map(|x| x.payload)
+    }
+
+    pub async fn top_releases(
+        &self,
+        username: String,
+        range: String,
+        count: usize,
+    ) -> Result<Vec<ReleaseStat>, ListenBrainzError> {
+        self.get::<ReleaseStatsResponse>(
+            format!("stats/{}", user_path(&username, "releases")),
+            &[("range", &range), ("count", &count.to_string())],
+        )
+        .await
+        .map(|x| x.payload.releases)
+    }
+
+    pub async fn top_recordings(
+        &self,
+        username: String,
+        range: String,
+        count: usize,
+    ) -> Result<Vec<RecordingStat>, ListenBrainzError> {
+        self.get::<RecordingStatsResponse>(
+            format!("stats/{}", user_path(&username, "recordings")),
+            &[("range", &range), ("count", &count.to_string())],
+        )
+        .await
+        .map(|x| x.payload.recordings)
+    }
+
+    /// How many times `username` listened to `artist` across all time, from the artist stats.
+    /// Every row that credits the artist counts, so listens where they're featured are included,
+    /// the same as when listens in a range are counted one by one.
+    pub async fn artist_listen_count(
+        &self,
+        username: String,
+        artist: String,
+    ) -> Result<u64, ListenBrainzError> {
+        let mut offset = 0;
+        let mut count = 0;
+        loop {
+            let stats = self
+                .top_artists(
+                    username.clone(),
+                    "all_time".to_string(),
+                    LISTENBRAINZ_MAX_ITEMS,
+                    offset,
+                )
+                .await?;
+            count += stats
+                .artists
+                .iter()
+                .filter(|x| credits(&x.artist_name, &artist, false))
+                .map(|x| x.listen_count)
+                .sum::<u64>();
+            offset += LISTENBRAINZ_MAX_ITEMS;
+            if stats.artists.is_empty() || offset as u64 >= stats.total_artist_count {
+                return Ok(count);
+            }
+        }
+    }
+}
+
+/// Cover art for a release, from the Cover Art Archive.
+pub fn release_art_url(release_mbid: &str) -> String {
+    format!(
+        "{}release/{}/front-250",
+        COVER_ART_ARCHIVE_BASE, release_mbid
+    )
+}
+
+impl ReleaseStat {
+    pub fn art_url(&self) -> Option<String> {
+        self.caa_release_mbid
+            .as_ref()
+            .or(self.release_mbid.as_ref())
+            .map(|x| release_art_url(x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usernames_are_escaped_in_paths() {
+        assert_eq!(user_path("rob_b-1", "listens"), "user/rob_b-1/listens");
+        assert_eq!(
+            user_path("a/../b?c#d", "listens"),
+            "user/a%2F%2E%2E%2Fb%3Fc%23d/listens"
+        );
+        assert_eq!(
+            user_path("Björk fan", "listens"),
+            "user/Bj%C3%B6rk%20fan/listens"
+        );
+    }
+
+    #[test]
+    fn dot_names_are_invalid() {
+        assert!(!is_valid_username(""));
+        assert!(!is_valid_username("."));
+        assert!(!is_valid_username(".."));
+        assert!(is_valid_username("a.b"));
+    }
+}
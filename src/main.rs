@@ -1,6 +1,8 @@
 #[allow(unused_imports)]
 use clap::{Arg, Command};
 use mljboard_bot::discord::bot::*;
+use mljboard_bot::lfm::LastFMApi;
+use mljboard_bot::listenbrainz::ListenBrainzApi;
 use poise::serenity_prelude::*;
 use sqlx::{Executor, PgPool};
 #[allow(unused_imports)]
@@ -14,6 +16,10 @@ pub async fn run(
     hos_server_https: bool,
     pool: PgPool,
     lastfm_api: Option<String>,
+    lastfm_secret: Option<String>,
+    lastfm_api_base: Option<String>,
+    lastfm_auth_url: Option<String>,
+    listenbrainz_api_base: Option<String>,
 ) -> poise::serenity_prelude::client::ClientBuilder {
     log::info!("Connecting to database");
 
@@ -27,26 +33,65 @@ pub async fn run(
                 hos_setup(),
                 website_setup(),
                 lfm_setup(),
+                lfm_verify(),
+                lb_setup(),
                 reset(),
                 scrobbles(),
                 artistscrobbles(),
+                trackscrobbles(),
+                albumscrobbles(),
                 lfmuser(),
                 grid(),
+                sync_status(),
+                privacy(),
+                nowplaying(),
+                recent(),
+                top(),
+                compare(),
+                whoknows(),
+                crowns(),
+                streak(),
+                calendar(),
+                heatmap(),
+                wrapped(),
+                milestone(),
+                milestones(),
+                timezone(),
             ],
+            pre_command: |ctx| Box::pin(track_guild_member(ctx)),
             ..Default::default()
         })
         .setup(move |ctx, ready, framework| {
             Box::pin(async move {
                 log::info!("{} is connected!", format_user(ready.user.clone().into()));
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
+                let reqwest_client = reqwest::Client::builder().build().unwrap();
+                let lastfm = lastfm_api.map(|api_key| {
+                    LastFMApi::new(
+                        reqwest_client.clone(),
+                        api_key,
+                        lastfm_secret,
+                        lastfm_api_base,
+                        lastfm_auth_url,
+                    )
+                });
+                if let Some(lastfm) = lastfm.clone() {
+                    tokio::spawn(mljboard_bot::lfm::sync::run_sync_worker(
+                        pool.clone(),
+                        lastfm,
+                        ctx.http.clone(),
+                    ));
+                }
                 Ok(BotData {
                     pool,
                     hos_server_ip,
                     hos_server_port,
                     hos_server_passwd,
                     hos_server_https,
-                    reqwest_client: reqwest::Client::builder().build().unwrap(),
-                    lastfm_api,
+                    reqwest_client: reqwest_client.clone(),
+                    lastfm,
+                    listenbrainz: ListenBrainzApi::new(reqwest_client, listenbrainz_api_base),
+                    library_cache: Default::default(),
                 })
             })
         })
@@ -104,6 +149,32 @@ pub async fn main() {
                 .value_name("LFM_API")
                 .help("Last.FM API key, for operations between mljboard-bot and Last.FM"),
         )
+        .arg(
+            Arg::new("lfm_secret")
+                .long("lfm-secret")
+                .value_name("LFM_SECRET")
+                .help(
+                    "Last.FM API shared secret, needed for users to verify their Last.FM accounts",
+                ),
+        )
+        .arg(
+            Arg::new("lfm_api_base")
+                .long("lfm-api-base")
+                .value_name("LFM_API_BASE")
+                .help("Last.FM API base URL. Defaults to https://ws.audioscrobbler.com/2.0/"),
+        )
+        .arg(
+            Arg::new("lfm_auth_url")
+                .long("lfm-auth-url")
+                .value_name("LFM_AUTH_URL")
+                .help("Last.FM web authentication URL. Defaults to https://www.last.fm/api/auth/"),
+        )
+        .arg(
+            Arg::new("lb_api_base")
+                .long("lb-api-base")
+                .value_name("LB_API_BASE")
+                .help("ListenBrainz API base URL. Defaults to https://api.listenbrainz.org/1/"),
+        )
         .get_matches();
 
     let bot_token = matches.get_one::<String>("bot_token").unwrap().to_string();
@@ -129,6 +200,22 @@ pub async fn main() {
 
     let lastfm_api: Option<String> = matches.get_one::<String>("lfm_api").map(|x| x.to_string());
 
+    let lastfm_secret: Option<String> = matches
+        .get_one::<String>("lfm_secret")
+        .map(|x| x.to_string());
+
+    let lastfm_api_base: Option<String> = matches
+        .get_one::<String>("lfm_api_base")
+        .map(|x| x.to_string());
+
+    let lastfm_auth_url: Option<String> = matches
+        .get_one::<String>("lfm_auth_url")
+        .map(|x| x.to_string());
+
+    let listenbrainz_api_base: Option<String> = matches
+        .get_one::<String>("lb_api_base")
+        .map(|x| x.to_string());
+
     let pool = mljboard_bot::db::postgres::start_db(postgres_url)
         .await
         .expect("Postgres connection failed");
@@ -141,6 +228,10 @@ pub async fn main() {
         hos_server_https,
         pool,
         lastfm_api,
+        lastfm_secret,
+        lastfm_api_base,
+        lastfm_auth_url,
+        listenbrainz_api_base,
     )
     .await;
 
@@ -168,6 +259,10 @@ async fn serenity(
     let hos_server_passwd: Option<String> = secret_store.get("HOS_PASSWD");
     let hos_server_https: bool = secret_store.get("HOS_HTTPS").unwrap() == "yes"; // hoping for a `bool` option in the secret store
     let lastfm_api = secret_store.get("LFM_API");
+    let lastfm_secret = secret_store.get("LFM_SECRET").filter(|x| !x.is_empty());
+    let lastfm_api_base = secret_store.get("LFM_API_BASE");
+    let lastfm_auth_url = secret_store.get("LFM_AUTH_URL");
+    let listenbrainz_api_base = secret_store.get("LB_API_BASE");
 
     let client_builder = run(
         bot_token,
@@ -177,6 +272,10 @@ async fn serenity(
         hos_server_https,
         pool,
         lastfm_api,
+        lastfm_secret,
+        lastfm_api_base,
+        lastfm_auth_url,
+        listenbrainz_api_base,
     )
     .await;
     let client: serenity::Client = client_builder.await.unwrap();
@@ -0,0 +1,172 @@
+use unicode_normalization::UnicodeNormalization;
+
+/// Dropped from the start of names, so "The Beatles" and "Beatles" are the same artist.
+/// Only "the", plenty of names start with "A" or "An" and aren't the same without it, like "A Tribe Called Quest".
+const LEADING_ARTICLES: [&str; 1] = ["the "];
+/// What comes between the main artist and the featured ones in a credit.
+const FEATURE_MARKERS: [&str; 5] = [" feat. ", " feat ", " ft. ", " ft ", " featuring "];
+/// What comes between featured artists. The main part of a credit isn't split on these,
+/// since plenty of artists are called something like "Simon & Garfunkel".
+const FEATURED_SEPARATORS: [&str; 3] = [", ", " & ", " and "];
+
+/// `name` the way it's compared: Unicode compatibility forms folded together (full-width letters,
+/// ligatures and composed vs decomposed accents), lowercased, whitespace collapsed and a leading article dropped.
+pub fn normalize(name: &str) -> String {
+    let folded = name.nfkc().collect::<String>().to_lowercase();
+    let collapsed = folded.split_whitespace().collect::<Vec<&str>>().join(" ");
+    for article in LEADING_ARTICLES {
+        if let Some(rest) = collapsed.strip_prefix(article) {
+            return rest.to_string();
+        }
+    }
+    collapsed
+}
+
+/// Every artist in a credit like "A feat. B & C" or "A (ft. B)", main artist first, as written.
+pub fn split_artists(credit: &str) -> Vec<String> {
+    let credit: String = credit
+        .chars()
+        .map(|c| match c {
+            '(' | ')' | '[' | ']' => ' ',
+            _ => c,
+        })
+        .collect();
+    // ASCII lowercasing keeps byte offsets the same, so they can be used on `credit`
+    let lower = credit.to_ascii_lowercase();
+    let cut = FEATURE_MARKERS
+        .iter()
+        .filter_map(|marker| lower.find(marker).map(|at| (at, marker.len())))
+        .min();
+
+    let (main, featured) = match cut {
+        Some((at, len)) => (&credit[..at], &credit[at + len..]),
+        None => (credit.as_str(), ""),
+    };
+    let mut artists = vec![main.trim().to_string()];
+    let mut featured = vec![featured.to_string()];
+    for separator in FEATURED_SEPARATORS {
+        featured = featured
+            .iter()
+            .flat_map(|part| {
+                let lower = part.to_ascii_lowercase();
+                let mut pieces = vec![];
+                let mut start = 0;
+                while let Some(at) = lower[start..].find(separator) {
+                    pieces.push(part[start..start + at].to_string());
+                    start += at + separator.len();
+                }
+                pieces.push(part[start..].to_string());
+                pieces
+            })
+            .collect();
+    }
+    artists.extend(featured.into_iter().map(|x| x.trim().to_string()));
+    artists.retain(|x| !x.is_empty());
+    artists
+}
+
+/// The main artist of a credit, normalized. Two names with the same key are the same artist.
+pub fn artist_key(credit: &str) -> String {
+    normalize(
+        split_artists(credit)
+            .first()
+            .map_or(credit, |main| main.as_str()),
+    )
+}
+
+/// Whether `artist` is anywhere in `credit`, featured or not. `comma_joined` is for credits from Maloja,
+/// which shows several artists on a scrobble joined with ", ". Anywhere else a comma is part of a name,
+/// like in "Tyler, The Creator", and splitting there would make "Creator" match it.
+pub fn credits(credit: &str, artist: &str, comma_joined: bool) -> bool {
+    let artist = artist_key(artist);
+    (comma_joined && credit.split(", ").any(|part| normalize(part) == artist))
+        || split_artists(credit)
+            .iter()
+            .any(|part| normalize(part) == artist)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_folds_case_width_and_accents() {
+        assert_eq!(normalize("  The   Beatles "), "beatles");
+        assert_eq!(normalize("ＲＡＤＩＯＨＥＡＤ"), "radiohead");
+        // composed and decomposed "é"
+        assert_eq!(normalize("Beyonc\u{e9}"), normalize("Beyonce\u{301}"));
+        assert_eq!(normalize("ﬁve"), "five");
+    }
+
+    #[test]
+    fn only_the_is_dropped() {
+        assert_eq!(normalize("The The"), "the");
+        assert_eq!(normalize("A Tribe Called Quest"), "a tribe called quest");
+        assert_eq!(normalize("An Horse"), "an horse");
+        assert_eq!(normalize("Theatre of Tragedy"), "theatre of tragedy");
+    }
+
+    #[test]
+    fn split_artists_finds_featured_artists() {
+        assert_eq!(split_artists("Daft Punk"), vec!["Daft Punk"]);
+        assert_eq!(
+            split_artists("Daft Punk feat. Pharrell Williams & Nile Rodgers"),
+            vec!["Daft Punk", "Pharrell Williams", "Nile Rodgers"]
+        );
+        assert_eq!(
+            split_artists("Calvin Harris (ft. Rihanna)"),
+            vec!["Calvin Harris", "Rihanna"]
+        );
+        assert_eq!(
+            split_artists("A FEATURING B, C and D"),
+            vec!["A", "B", "C", "D"]
+        );
+    }
+
+    #[test]
+    fn split_artists_keeps_the_main_credit_whole() {
+        assert_eq!(
+            split_artists("Simon & Garfunkel"),
+            vec!["Simon & Garfunkel"]
+        );
+        assert_eq!(
+            split_artists("Earth, Wind & Fire feat. The Emotions"),
+            vec!["Earth, Wind & Fire", "The Emotions"]
+        );
+    }
+
+    #[test]
+    fn artist_key_is_the_main_artist() {
+        assert_eq!(artist_key("The Weeknd feat. Daft Punk"), "weeknd");
+        assert_eq!(artist_key("weeknd"), artist_key("THE WEEKND"));
+        assert_eq!(artist_key("Tyler, The Creator"), "tyler, the creator");
+    }
+
+    #[test]
+    fn credits_matches_main_and_featured_artists() {
+        assert!(credits(
+            "Daft Punk feat. Pharrell Williams",
+            "daft punk",
+            false
+        ));
+        assert!(credits(
+            "Daft Punk feat. Pharrell Williams",
+            "Pharrell Williams",
+            false
+        ));
+        assert!(!credits(
+            "Daft Punk feat. Pharrell Williams",
+            "Pharrell",
+            false
+        ));
+    }
+
+    #[test]
+    fn commas_only_split_maloja_credits() {
+        assert!(!credits("Tyler, The Creator", "Creator", false));
+        assert!(!credits("Earth, Wind & Fire", "Earth", false));
+        assert!(credits("Tyler, The Creator", "Tyler, The Creator", false));
+        assert!(credits("Kanye West, Jay-Z", "Jay-Z", true));
+        assert!(!credits("Kanye West, Jay-Z", "Jay-Z", false));
+    }
+}
@@ -0,0 +1,394 @@
+use crate::discord::lastfm::LfmRange;
+use chrono::{Datelike, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use mljcl::range::Range as MljRange;
+use std::{fmt, str::FromStr};
+
+pub const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// A span of time to count scrobbles in, understood the same way by every backend.
+/// "This week", "this month" and "this year" are calendar periods, not rolling windows.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StatsRange {
+    Today,
+    Week,
+    Month,
+    Year,
+    AllTime,
+    CalendarMonth {
+        year: i32,
+        month: u32,
+    },
+    CalendarYear(i32),
+    /// From the start of `start` to the end of `end`.
+    Custom {
+        start: NaiveDate,
+        end: NaiveDate,
+    },
+}
+
+#[derive(Debug)]
+pub struct RangeParseError(String);
+
+impl fmt::Display for RangeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Couldn't understand the range \"{}\". Try today, week, month, year, all, 2024, March 2024, 2024-03 or 2024-01-01..2024-02-15",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for RangeParseError {}
+
+fn month_from_name(name: &str) -> Option<u32> {
+    // three letters is enough to tell months apart
+    if name.len() < 3 {
+        return None;
+    }
+    MONTH_NAMES
+        .iter()
+        .position(|month| month.to_lowercase().starts_with(name))
+        .map(|x| x as u32 + 1)
+}
+
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(date, "%Y/%m/%d"))
+        .ok()
+}
+
+impl FromStr for StatsRange {
+    type Err = RangeParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let input = s.trim().to_lowercase();
+        let input = input.strip_prefix("this ").unwrap_or(&input);
+        let err = || RangeParseError(s.to_string());
+
+        match input {
+            "today" | "day" => return Ok(StatsRange::Today),
+            "week" | "thisweek" => return Ok(StatsRange::Week),
+            "month" | "thismonth" => return Ok(StatsRange::Month),
+            "year" | "thisyear" => return Ok(StatsRange::Year),
+            "all" | "all time" | "alltime" | "overall" => return Ok(StatsRange::AllTime),
+            _ => {}
+        }
+
+        if let Some((start, end)) = input.split_once("..").or_else(|| input.split_once(" to ")) {
+            let start = parse_date(start.trim()).ok_or_else(err)?;
+            let end = parse_date(end.trim()).ok_or_else(err)?;
+            if end < start || end.succ_opt().is_none() {
+                return Err(err());
+            }
+            return Ok(StatsRange::Custom { start, end });
+        }
+
+        // years past what dates can hold would have no bounds, and be counted as all time
+        if let Ok(year) = input.parse::<i32>() {
+            year_days(year).ok_or_else(err)?;
+            return Ok(StatsRange::CalendarYear(year));
+        }
+
+        // 2024-03, 2024/03, March 2024 or Mar 2024
+        let (first, second) = input
+            .split_once(['-', '/', ' '])
+            .map(|(a, b)| (a.trim(), b.trim()))
+            .ok_or_else(err)?;
+        let (year, month) = match (first.parse::<i32>(), second.parse::<u32>()) {
+            (Ok(year), Ok(month)) => (year, month),
+            _ => (
+                second.parse::<i32>().map_err(|_| err())?,
+                month_from_name(first).ok_or_else(err)?,
+            ),
+        };
+        month_days(year, month).ok_or_else(err)?;
+        Ok(StatsRange::CalendarMonth { year, month })
+    }
+}
+
+impl fmt::Display for StatsRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatsRange::Today => write!(f, "Today"),
+            StatsRange::Week => write!(f, "This week"),
+            StatsRange::Month => write!(f, "This month"),
+            StatsRange::Year => write!(f, "This year"),
+            StatsRange::AllTime => write!(f, "All time"),
+            StatsRange::CalendarMonth { year, month } => {
+                write!(f, "{} {}", MONTH_NAMES[*month as usize - 1], year)
+            }
+            StatsRange::CalendarYear(year) => write!(f, "{}", year),
+            StatsRange::Custom { start, end } => write!(f, "{} to {}", start, end),
+        }
+    }
+}
+
+fn month_days(year: i32, month: u32) -> Option<(NaiveDate, NaiveDate)> {
+    let start = NaiveDate::from_ymd_opt(year, month, 1)?;
+    let end = match month {
+        12 => NaiveDate::from_ymd_opt(year + 1, 1, 1)?,
+        _ => NaiveDate::from_ymd_opt(year, month + 1, 1)?,
+    };
+    Some((start, end))
+}
+
+fn year_days(year: i32) -> Option<(NaiveDate, NaiveDate)> {
+    Some((
+        NaiveDate::from_ymd_opt(year, 1, 1)?,
+        NaiveDate::from_ymd_opt(year + 1, 1, 1)?,
+    ))
+}
+
+/// Today's date for someone in `tz`, UTC if they haven't set a timezone.
+pub fn today(tz: Option<Tz>) -> NaiveDate {
+    match tz {
+        Some(tz) => Utc::now().with_timezone(&tz).date_naive(),
+        None => Utc::now().date_naive(),
+    }
+}
+
+/// The unix timestamp `date` starts at in `tz`.
+pub fn midnight(date: NaiveDate, tz: Option<Tz>) -> i64 {
+    let naive = date.and_hms_opt(0, 0, 0).unwrap();
+    match tz {
+        // some timezones skip midnight for daylight saving, then the day starts an hour later
+        Some(tz) => tz
+            .from_local_datetime(&naive)
+            .earliest()
+            .or_else(|| {
+                tz.from_local_datetime(&(naive + Duration::hours(1)))
+                    .earliest()
+            })
+            .map(|x| x.timestamp())
+            .unwrap_or(naive.and_utc().timestamp()),
+        None => naive.and_utc().timestamp(),
+    }
+}
+
+impl StatsRange {
+    /// The first day in the range and the first day after it, `None` for all time.
+    pub fn days(&self, today: NaiveDate) -> Option<(NaiveDate, NaiveDate)> {
+        match self {
+            StatsRange::Today => Some((today, today.succ_opt()?)),
+            StatsRange::Week => {
+                let start = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+                Some((start, start + Duration::days(7)))
+            }
+            StatsRange::Month => month_days(today.year(), today.month()),
+            StatsRange::Year => year_days(today.year()),
+            StatsRange::AllTime => None,
+            StatsRange::CalendarMonth { year, month } => month_days(*year, *month),
+            StatsRange::CalendarYear(year) => year_days(*year),
+            StatsRange::Custom { start, end } => Some((*start, end.succ_opt()?)),
+        }
+    }
+
+    /// Unix timestamps of the first and last second of the range, with days counted in `tz`.
+    pub fn bounds(&self, tz: Option<Tz>) -> LfmRange {
+        match self.days(today(tz)) {
+            Some((start, end)) => {
+                LfmRange::new(Some(midnight(start, tz)), Some(midnight(end, tz) - 1))
+            }
+            None => LfmRange::new(None, None),
+        }
+    }
+
+    /// The same range in Maloja's terms, `None` when Maloja can't express it.
+    /// Maloja counts days in its server's timezone, so once a user has set their own only all time is left to it.
+    /// Weeks start on a day that depends on the server too, so they're always counted from Monday here instead.
+    pub fn maloja(&self, tz: Option<Tz>) -> Option<MljRange> {
+        if tz.is_some() && *self != StatsRange::AllTime {
+            return None;
+        }
+        match self {
+            StatsRange::Today => Some(MljRange::In("today".to_string())),
+            StatsRange::Week => None,
+            StatsRange::Month => Some(MljRange::In("thismonth".to_string())),
+            StatsRange::Year => Some(MljRange::In("thisyear".to_string())),
+            StatsRange::AllTime => Some(MljRange::AllTime),
+            StatsRange::CalendarMonth { year, month } => {
+                Some(MljRange::In(format!("{}/{:02}", year, month)))
+            }
+            StatsRange::CalendarYear(year) => Some(MljRange::In(year.to_string())),
+            StatsRange::Custom { .. } => None,
+        }
+    }
+
+    /// The smallest Maloja range that contains this one, for fetching scrobbles to filter by timestamp.
+    /// It's a day wider on both ends so no timezone difference can push a scrobble out of it.
+    pub fn maloja_covering(&self, tz: Option<Tz>) -> MljRange {
+        let Some((start, end)) = self.days(today(tz)) else {
+            return MljRange::AllTime;
+        };
+        let Some(start) = start.pred_opt() else {
+            return MljRange::AllTime;
+        };
+        if (start.year(), start.month()) == (end.year(), end.month()) {
+            MljRange::In(format!("{}/{:02}", start.year(), start.month()))
+        } else if start.year() == end.year() {
+            MljRange::In(start.year().to_string())
+        } else {
+            MljRange::AllTime
+        }
+    }
+
+    /// The ListenBrainz stats range for this range, `None` when ListenBrainz doesn't calculate one.
+    /// Those are in UTC too, so they're only used for users without a timezone.
+    pub fn listenbrainz(&self, tz: Option<Tz>) -> Option<String> {
+        if tz.is_some() && *self != StatsRange::AllTime {
+            return None;
+        }
+        match self {
+            StatsRange::Week => Some("this_week".to_string()),
+            StatsRange::Month => Some("this_month".to_string()),
+            StatsRange::Year => Some("this_year".to_string()),
+            StatsRange::AllTime => Some("all_time".to_string()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn parses_named_ranges() {
+        assert_eq!("today".parse::<StatsRange>().unwrap(), StatsRange::Today);
+        assert_eq!("This Week".parse::<StatsRange>().unwrap(), StatsRange::Week);
+        assert_eq!(
+            "thismonth".parse::<StatsRange>().unwrap(),
+            StatsRange::Month
+        );
+        assert_eq!(" year ".parse::<StatsRange>().unwrap(), StatsRange::Year);
+        assert_eq!(
+            "all time".parse::<StatsRange>().unwrap(),
+            StatsRange::AllTime
+        );
+    }
+
+    #[test]
+    fn parses_calendar_ranges() {
+        assert_eq!(
+            "2024".parse::<StatsRange>().unwrap(),
+            StatsRange::CalendarYear(2024)
+        );
+        let march = StatsRange::CalendarMonth {
+            year: 2024,
+            month: 3,
+        };
+        assert_eq!("2024-03".parse::<StatsRange>().unwrap(), march);
+        assert_eq!("2024/3".parse::<StatsRange>().unwrap(), march);
+        assert_eq!("March 2024".parse::<StatsRange>().unwrap(), march);
+        assert_eq!("mar 2024".parse::<StatsRange>().unwrap(), march);
+        assert_eq!(
+            "2024-01-01..2024-02-15".parse::<StatsRange>().unwrap(),
+            StatsRange::Custom {
+                start: date(2024, 1, 1),
+                end: date(2024, 2, 15),
+            }
+        );
+        assert_eq!(
+            "2024/01/01 to 2024/01/01".parse::<StatsRange>().unwrap(),
+            StatsRange::Custom {
+                start: date(2024, 1, 1),
+                end: date(2024, 1, 1),
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_nonsense() {
+        for input in [
+            "",
+            "fortnight",
+            "ma 2024",
+            "2024-13",
+            "2024-00",
+            "2024-02-15..2024-01-01",
+            "999999",
+            "March 999999",
+        ] {
+            assert!(input.parse::<StatsRange>().is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        // 2024-03-14 is a Thursday
+        assert_eq!(
+            StatsRange::Week.days(date(2024, 3, 14)),
+            Some((date(2024, 3, 11), date(2024, 3, 18)))
+        );
+        assert_eq!(
+            StatsRange::Week.days(date(2024, 3, 11)),
+            Some((date(2024, 3, 11), date(2024, 3, 18)))
+        );
+        assert_eq!(
+            StatsRange::Week.days(date(2024, 3, 17)),
+            Some((date(2024, 3, 11), date(2024, 3, 18)))
+        );
+    }
+
+    #[test]
+    fn calendar_days() {
+        let today = date(2024, 2, 10);
+        assert_eq!(
+            StatsRange::Today.days(today),
+            Some((today, date(2024, 2, 11)))
+        );
+        assert_eq!(
+            StatsRange::Month.days(today),
+            Some((date(2024, 2, 1), date(2024, 3, 1)))
+        );
+        assert_eq!(
+            StatsRange::Year.days(today),
+            Some((date(2024, 1, 1), date(2025, 1, 1)))
+        );
+        assert_eq!(
+            StatsRange::CalendarMonth {
+                year: 2023,
+                month: 12
+            }
+            .days(today),
+            Some((date(2023, 12, 1), date(2024, 1, 1)))
+        );
+        assert_eq!(StatsRange::AllTime.days(today), None);
+    }
+
+    #[test]
+    fn bounds_cover_whole_days() {
+        let bounds = StatsRange::CalendarYear(2024).bounds(None);
+        assert_eq!(bounds.start, Some(1704067200));
+        assert_eq!(bounds.end, Some(1735689600 - 1));
+        assert!(!StatsRange::AllTime.bounds(None).available());
+    }
+
+    #[test]
+    fn maloja_never_counts_weeks() {
+        assert!(StatsRange::Week.maloja(None).is_none());
+        assert!(StatsRange::Month.maloja(None).is_some());
+        assert!(StatsRange::Month
+            .maloja(Some(chrono_tz::Europe::Berlin))
+            .is_none());
+    }
+}